@@ -0,0 +1,340 @@
+use crate::storage::Storage;
+use crate::types::{BitRange, BulkString, RedisKey, RespValue};
+use crate::value::Value;
+
+/// Bits are numbered from the most significant bit of the first byte, like in redis
+fn mask(offset: usize) -> u8 {
+    0x80 >> (offset % 8)
+}
+
+/// Set or clear the bit at `offset`, the string is created or padded with zeros when it's
+/// shorter. Replies the previous bit
+pub fn setbit(
+    storage: &mut Storage,
+    key: &RedisKey,
+    offset: usize,
+    bit: bool,
+) -> Result<RespValue, &'static str> {
+    let string = storage
+        .get_or_insert_with(key, || Value::String(BulkString(vec![])))
+        .as_string_mut()?;
+    let byte = offset / 8;
+    if string.0.len() <= byte {
+        string.0.resize(byte + 1, 0);
+    }
+    let previous = string.0[byte] & mask(offset) != 0;
+    if bit {
+        string.0[byte] |= mask(offset);
+    } else {
+        string.0[byte] &= !mask(offset);
+    }
+    Ok(RespValue::Integer(previous as i64))
+}
+
+/// The bit at `offset`, 0 past the end of the string or when the key doesn't exist
+pub fn getbit(
+    storage: &mut Storage,
+    key: &RedisKey,
+    offset: usize,
+) -> Result<RespValue, &'static str> {
+    let bit = match storage.get(key) {
+        Some(entry) => entry
+            .value
+            .as_string()?
+            .0
+            .get(offset / 8)
+            .is_some_and(|byte| byte & mask(offset) != 0),
+        None => false,
+    };
+    Ok(RespValue::Integer(bit as i64))
+}
+
+/// Resolve a range of a string of `len` bytes to the inclusive positions of its first and last
+/// bits, `None` if it's empty
+/// Negative indexes start from the end, unlike list ranges both ends are clamped to the string
+fn bit_range(len: usize, range: &BitRange) -> Option<(usize, usize)> {
+    let len = if range.bits { len * 8 } else { len } as i64;
+    let start = range.start;
+    let end = range.end.unwrap_or(-1);
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if start > end {
+        return None;
+    }
+    let (start, end) = (start as usize, end as usize);
+    if range.bits {
+        Some((start, end))
+    } else {
+        Some((start * 8, end * 8 + 7))
+    }
+}
+
+/// Bytes of a string covering the bits from `first` to `last`, with their position and a mask of
+/// the bits inside the range
+fn masked_bytes(
+    string: &[u8],
+    first: usize,
+    last: usize,
+) -> impl Iterator<Item = (usize, u8, u8)> + '_ {
+    (first / 8..=last / 8).map(move |position| {
+        let mut mask = 0xff;
+        if position == first / 8 {
+            mask &= 0xff >> (first % 8);
+        }
+        if position == last / 8 {
+            mask &= 0xff << (7 - last % 8);
+        }
+        (position, string[position], mask)
+    })
+}
+
+/// Number of bits set in a range of a string
+pub fn bitcount(
+    storage: &mut Storage,
+    key: &RedisKey,
+    range: &BitRange,
+) -> Result<RespValue, &'static str> {
+    let string = match storage.get(key) {
+        Some(entry) => &entry.value.as_string()?.0,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let count = match bit_range(string.len(), range) {
+        Some((first, last)) => masked_bytes(string, first, last)
+            .map(|(_, byte, mask)| (byte & mask).count_ones())
+            .sum(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(count as i64))
+}
+
+/// Position of the first bit set to `bit` in a range of a string, or -1
+/// A missing key is an empty string of clear bits. When searching a clear bit without the end of
+/// the range, the string is considered padded with clear bits, like in redis
+pub fn bitpos(
+    storage: &mut Storage,
+    key: &RedisKey,
+    bit: bool,
+    range: &BitRange,
+) -> Result<RespValue, &'static str> {
+    let string = match storage.get(key) {
+        Some(entry) => &entry.value.as_string()?.0,
+        None => return Ok(RespValue::Integer(if bit { -1 } else { 0 })),
+    };
+    let (first, last) = match bit_range(string.len(), range) {
+        Some(positions) => positions,
+        None => return Ok(RespValue::Integer(-1)),
+    };
+    let found = masked_bytes(string, first, last).find_map(|(position, byte, mask)| {
+        // Search the set bits of the byte, or of its complement for clear bits
+        let byte = if bit { byte } else { !byte } & mask;
+        if byte == 0 {
+            None
+        } else {
+            Some(position * 8 + byte.leading_zeros() as usize)
+        }
+    });
+    let position = match found {
+        Some(position) => position as i64,
+        None if !bit && range.end.is_none() => last as i64 + 1,
+        None => -1,
+    };
+    Ok(RespValue::Integer(position))
+}
+
+/// Bitwise operations of BITOP
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    And,
+    Or,
+    Xor,
+    /// Complement of a single string
+    Not,
+}
+
+/// Combine the strings of several keys byte by byte into `destination`, the destination is
+/// removed if the result is empty
+/// Shorter strings and missing keys are padded with zeros up to the longest string
+/// Replies the length of the result
+pub fn bitop(
+    storage: &mut Storage,
+    operation: Operation,
+    destination: &RedisKey,
+    keys: &[RedisKey],
+) -> Result<RespValue, &'static str> {
+    let mut strings = Vec::with_capacity(keys.len());
+    for entry in storage.get_many(keys) {
+        match entry {
+            Some(entry) => strings.push(entry.value.as_string()?.0.as_slice()),
+            None => strings.push(&[]),
+        }
+    }
+    let len = strings.iter().map(|string| string.len()).max().unwrap_or(0);
+    let byte = |string: &[u8], position: usize| string.get(position).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|position| {
+            let mut bytes = strings.iter().map(|string| byte(string, position));
+            let first = bytes.next().unwrap_or(0);
+            match operation {
+                Operation::And => bytes.fold(first, |result, byte| result & byte),
+                Operation::Or => bytes.fold(first, |result, byte| result | byte),
+                Operation::Xor => bytes.fold(first, |result, byte| result ^ byte),
+                Operation::Not => !first,
+            }
+        })
+        .collect();
+    if result.is_empty() {
+        storage.remove(destination);
+    } else {
+        storage.set(destination.clone(), Value::String(BulkString(result)));
+    }
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Integer type of BITFIELD, up to 64 bits signed or 63 bits unsigned so values fit an i64
+#[derive(Debug, Clone, Copy)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl BitFieldType {
+    /// Lowest and highest values of the type
+    fn limits(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+
+    /// Read the integer at the bit `offset`, bits past the end of the string are clear
+    fn get(&self, string: &[u8], offset: usize) -> i64 {
+        let value = (offset..offset + self.bits as usize).fold(0u64, |value, position| {
+            let bit = string
+                .get(position / 8)
+                .is_some_and(|byte| byte & mask(position) != 0);
+            value << 1 | bit as u64
+        });
+        if self.signed {
+            // Sign extension, moving the sign bit of the type to the sign bit of the i64
+            let shift = 64 - self.bits;
+            (value << shift) as i64 >> shift
+        } else {
+            value as i64
+        }
+    }
+
+    /// Write the lowest bits of `value` at the bit `offset`, the string must be long enough
+    fn set(&self, string: &mut [u8], offset: usize, value: i64) {
+        for (shift, position) in (offset..offset + self.bits as usize).rev().enumerate() {
+            if value >> shift & 1 == 1 {
+                string[position / 8] |= mask(position);
+            } else {
+                string[position / 8] &= !mask(position);
+            }
+        }
+    }
+
+    /// Fit a value in the type following `overflow`, `None` when it fails
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (lowest, highest) = self.limits();
+        if (lowest..=highest).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                if self.signed && wrapped > highest {
+                    Some((wrapped - (1 << self.bits)) as i64)
+                } else {
+                    Some(wrapped as i64)
+                }
+            }
+            Overflow::Sat => Some(value.max(lowest).min(highest) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+/// Behavior of BITFIELD writes when the value doesn't fit the type
+#[derive(Debug, Clone, Copy)]
+pub enum Overflow {
+    Wrap,
+    /// Saturate to the lowest or highest value
+    Sat,
+    /// Don't write, replying null
+    Fail,
+}
+
+/// Subcommands of BITFIELD, with the type and the bit offset of the integer
+#[derive(Debug)]
+pub enum BitFieldOp {
+    Get(BitFieldType, usize),
+    Set(BitFieldType, usize, i64),
+    IncrBy(BitFieldType, usize, i64),
+    /// Changes the overflow of the next writes
+    Overflow(Overflow),
+}
+
+/// Read and write integers of any width at any bit offset of a string
+/// Writes create the string, or pad it with zeros, to fit all of them before running
+/// Replies the value read by GET, the previous value for SET and the new one for INCRBY, or null
+/// when a write failed with OVERFLOW FAIL
+pub fn bitfield(
+    storage: &mut Storage,
+    key: &RedisKey,
+    operations: &[BitFieldOp],
+) -> Result<RespValue, &'static str> {
+    let len = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            BitFieldOp::Set(kind, offset, _) | BitFieldOp::IncrBy(kind, offset, _) => {
+                Some((offset + kind.bits as usize).div_ceil(8))
+            }
+            _ => None,
+        })
+        .max();
+    let mut empty = vec![];
+    let string = match len {
+        Some(len) => {
+            let string = &mut storage
+                .get_or_insert_with(key, || Value::String(BulkString(vec![])))
+                .as_string_mut()?
+                .0;
+            if string.len() < len {
+                string.resize(len, 0);
+            }
+            string
+        }
+        None => match storage.get_mut(key) {
+            Some(entry) => &mut entry.value.as_string_mut()?.0,
+            None => &mut empty,
+        },
+    };
+
+    let mut overflow = Overflow::Wrap;
+    let mut replies = Vec::new();
+    for operation in operations {
+        let reply = match *operation {
+            BitFieldOp::Get(kind, offset) => Some(kind.get(string, offset)),
+            BitFieldOp::Set(kind, offset, value) => {
+                let previous = kind.get(string, offset);
+                kind.fit(value as i128, overflow).map(|value| {
+                    kind.set(string, offset, value);
+                    previous
+                })
+            }
+            BitFieldOp::IncrBy(kind, offset, increment) => {
+                let value = kind.get(string, offset) as i128 + increment as i128;
+                kind.fit(value, overflow)
+                    .inspect(|&value| kind.set(string, offset, value))
+            }
+            BitFieldOp::Overflow(next) => {
+                overflow = next;
+                continue;
+            }
+        };
+        replies.push(reply.map_or(RespValue::Null, RespValue::Integer));
+    }
+    Ok(RespValue::Array(replies.into()))
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Data received from the client
+const INBOUND: u8 = b'>';
+/// Data sent to the client
+const OUTBOUND: u8 = b'<';
+
+/// Wraps the connection io and records the raw traffic to a capture file
+/// Every chunk read or written is stored as `<direction> <micros> <length>\r\n<data>\r\n` where
+/// direction is `>` for inbound data and `<` for outbound data, and micros is the time since the
+/// connection started
+pub struct Capture<T> {
+    io: T,
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl<T> Capture<T> {
+    pub fn new(io: T, path: &Path) -> io::Result<Capture<T>> {
+        Ok(Capture {
+            io,
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, direction: u8, data: &[u8]) {
+        let micros = self.start.elapsed().as_micros();
+        let result = write!(
+            self.file,
+            "{} {} {}\r\n",
+            direction as char,
+            micros,
+            data.len()
+        )
+        .and_then(|_| self.file.write_all(data))
+        .and_then(|_| self.file.write_all(b"\r\n"))
+        .and_then(|_| self.file.flush());
+        if let Err(err) = result {
+            warn!("Error writing capture: {:?}", err);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Capture<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.io).poll_read(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            this.record(INBOUND, &buf[..len]);
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Capture<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.io).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            this.record(OUTBOUND, &buf[..len]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// Fake connection used to replay a capture, reads the inbound data of the capture and keeps
+/// everything the server writes
+pub struct Replay {
+    input: io::Cursor<Vec<u8>>,
+    /// Outbound data recorded in the capture
+    pub expected: Vec<u8>,
+    /// Data written by the server during the replay
+    pub output: Vec<u8>,
+}
+
+impl Replay {
+    /// Load a capture file created by `Capture`
+    pub fn open(path: &Path) -> io::Result<Replay> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut input = Vec::new();
+        let mut expected = Vec::new();
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 {
+            let parts: Vec<_> = header.split_whitespace().collect();
+            let len: usize = match parts.as_slice() {
+                [_, _, len] => len.parse().map_err(|_| invalid("Invalid record length"))?,
+                _ => return Err(invalid("Invalid record header")),
+            };
+            let mut data = vec![0; len + 2];
+            reader.read_exact(&mut data)?;
+            data.truncate(len);
+            match parts[0].as_bytes() {
+                [INBOUND] => input.append(&mut data),
+                [OUTBOUND] => expected.append(&mut data),
+                _ => return Err(invalid("Invalid record direction")),
+            }
+            header.clear();
+        }
+
+        Ok(Replay {
+            input: io::Cursor::new(input),
+            expected,
+            output: Vec::new(),
+        })
+    }
+}
+
+impl AsyncRead for Replay {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().input.read(buf))
+    }
+}
+
+impl AsyncWrite for Replay {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().output.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::storage::{Databases, Storage};
+use crate::types::{RedisCmd, RedisKey};
+
+/// Id of the next connection
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State of a connection kept between its commands
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    /// Database used by the commands, changed with SELECT
+    pub db: usize,
+    /// Notified when a key the client is blocked on is created
+    pub ready: Arc<Notify>,
+    /// Set by a blocking command that had to wait, the connection retries it when it's ready
+    pub blocked: Option<Blocked>,
+}
+
+/// Blocking command waiting for some keys
+#[derive(Debug)]
+pub struct Blocked {
+    /// Command retried when one of the keys is created
+    pub command: RedisCmd,
+    pub db: usize,
+    pub keys: Vec<RedisKey>,
+    /// `None` waits forever
+    pub timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            db: 0,
+            ready: Arc::new(Notify::new()),
+            blocked: None,
+        }
+    }
+
+    /// Wait for any of the keys to be created before retrying the command
+    /// Called with the lock of the database held, so keys created right after the command are
+    /// not missed
+    pub fn block(
+        &mut self,
+        storage: &mut Storage,
+        command: RedisCmd,
+        keys: &[RedisKey],
+        timeout: Option<Duration>,
+    ) {
+        for key in keys {
+            storage.add_waiter(key, self.id, self.ready.clone());
+        }
+        self.blocked = Some(Blocked {
+            command,
+            db: self.db,
+            keys: keys.to_vec(),
+            timeout,
+        });
+    }
+
+    /// Stop waiting for the keys of a blocking command
+    pub fn unblock(&self, databases: &Databases, blocked: &Blocked) {
+        let mut storage = databases.select(blocked.db).lock().unwrap();
+        for key in &blocked.keys {
+            storage.remove_waiter(key, self.id);
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
@@ -11,21 +11,101 @@ use combine::{
     stream::{easy, PartialStream, RangeStream, StreamErrorFor},
     value, Parser,
 };
+use log::Level;
 use std::str;
 use tokio_util::codec::{Decoder, Encoder};
 
+use crate::config::Config;
+use crate::parser::RespParser;
 use crate::types::{BulkString, RespValue};
 
 pub struct RespCodec {
     pub state: AnySendPartialState,
+    parser: RespParser,
+    /// Accept inline commands, see `Config::inline_commands`
+    inline_commands: bool,
+    /// Decode with `resp_parser` instead of `RespParser`, see `Config::combine_parser`
+    combine_parser: bool,
+    /// Bytes of the current frame already consumed by the parser
+    pending: usize,
+    /// See `Config::client_query_buffer_limit`
+    query_buffer_limit: usize,
+    /// See `Config::proto_max_bulk_len`
+    max_bulk_len: usize,
 }
 
 impl RespCodec {
-    pub fn new() -> RespCodec {
+    pub fn new(config: &Config) -> RespCodec {
         RespCodec {
             state: Default::default(),
+            parser: RespParser::new(config.proto_max_bulk_len),
+            inline_commands: config.inline_commands,
+            combine_parser: config.combine_parser,
+            pending: 0,
+            query_buffer_limit: config.client_query_buffer_limit,
+            max_bulk_len: config.proto_max_bulk_len,
         }
     }
+
+    /// Decode using the combine parser
+    /// Errors are rare, so the input is parsed with the cheap error type and only parsed again
+    /// with detailed errors when it fails, see `describe_error`
+    fn decode_combine(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<RespValue>, Box<dyn std::error::Error + Send + Sync>> {
+        let (opt, removed_len) = match combine::stream::decode(
+            resp_parser(self.inline_commands, self.max_bulk_len),
+            &mut PartialStream(&src[..]),
+            &mut self.state,
+        ) {
+            Ok(result) => result,
+            Err(_) => return Err(self.describe_error(src).into()),
+        };
+
+        if log_enabled!(Level::Debug) {
+            debug!(
+                "Accepted {} bytes: `{:?}`",
+                removed_len,
+                str::from_utf8(&src[..removed_len]).unwrap_or("NOT UTF8")
+            );
+        }
+
+        // Remove the input we just consumed.
+        // Ideally this would be done automatically by the call to
+        // `stream::decode` but it does unfortunately not work due
+        // to lifetime issues (Non lexical lifetimes might fix it!)
+        src.advance(removed_len);
+
+        Ok(opt)
+    }
+
+    /// Parse the input again using easy errors to describe why it failed
+    /// The partial state is reset, so when the error happened in the middle of a frame the
+    /// description is relative to the input that was not consumed yet
+    fn describe_error(&self, src: &BytesMut) -> String {
+        let result = combine::stream::decode(
+            resp_parser(self.inline_commands, self.max_bulk_len),
+            &mut easy::Stream(PartialStream(&src[..])),
+            &mut Default::default(),
+        );
+        let description = match result {
+            Err(err) => err
+                .map_range(|r| {
+                    str::from_utf8(r)
+                        .ok()
+                        .map_or_else(|| format!("{:?}", r), |s| s.to_string())
+                })
+                .map_position(|p| p.translate_position(&src[..]))
+                .to_string(),
+            Ok(_) => "Parse error".into(),
+        };
+        format!(
+            "{}\nIn input: `{}`",
+            description,
+            String::from_utf8_lossy(src)
+        )
+    }
 }
 
 /// Line parser for resp protocol, reads until `\r\n`
@@ -63,7 +143,10 @@ where
 /// using Array of BulkStrings with the first element as the command
 /// That's why we only parse a subset of the resp2 protocol here, we only need to encode the rest
 /// of the spec to create anwsers to the clients
+/// When `inline_commands` is false only Arrays are accepted, anything else is a protocol error
 fn resp_parser<'a, Input>(
+    inline_commands: bool,
+    max_bulk_len: usize,
 ) -> impl Parser<Input, Output = RespValue, PartialState = AnySendPartialState> + 'a
 where
     Input: RangeStream<Token = u8, Range = &'a [u8]> + 'a,
@@ -82,17 +165,27 @@ where
     };
 
     // Binary friendly string
-    let bulk = || {
-        integer().then_partial(move |&mut length| {
-            if length < 0 {
-                value(RespValue::Null).left()
-            } else {
-                take(length as usize)
-                    .map(|data: &[u8]| RespValue::BulkString(BulkString(data.into())))
-                    .skip(range(&b"\r\n"[..]))
-                    .right()
-            }
-        })
+    let bulk = move || {
+        integer()
+            .and_then(move |length| {
+                if length > max_bulk_len as i64 {
+                    Err(StreamErrorFor::<Input>::message_static_message(
+                        "Invalid bulk length",
+                    ))
+                } else {
+                    Ok(length)
+                }
+            })
+            .then_partial(move |&mut length| {
+                if length < 0 {
+                    value(RespValue::Null).left()
+                } else {
+                    take(length as usize)
+                        .map(|data: &[u8]| RespValue::BulkString(BulkString(data.into())))
+                        .skip(range(&b"\r\n"[..]))
+                        .right()
+                }
+            })
     };
 
     // Array of bulk strings
@@ -115,13 +208,52 @@ where
         })
     };
 
-    any_send_partial_state(choice((byte(b'*').with(array()), simple_command())))
+    any_send_partial_state(if inline_commands {
+        choice((byte(b'*').with(array()), simple_command())).left()
+    } else {
+        byte(b'*').with(array()).right()
+    })
 }
 
-fn encode_string(prefix: u8, value: String, buf: &mut BytesMut) {
+/// Pre-encoded replies, common enough to be worth skipping the formatting
+const OK: &[u8] = b"+OK\r\n";
+const PONG: &[u8] = b"+PONG\r\n";
+const NULL: &[u8] = b"$-1\r\n";
+const NULL_ARRAY: &[u8] = b"*-1\r\n";
+const ZERO: &[u8] = b":0\r\n";
+const ONE: &[u8] = b":1\r\n";
+const EMPTY_ARRAY: &[u8] = b"*0\r\n";
+
+fn encode_string(prefix: u8, value: &str, buf: &mut BytesMut) {
     buf.reserve(value.len() + 3);
     buf.put_u8(prefix);
-    buf.put(&value.into_bytes()[..]);
+    buf.put(value.as_bytes());
+    buf.put(&b"\r\n"[..]);
+}
+
+/// Encode a prefixed integer line (ie. `:42\r\n` or `$5\r\n`), the digits are formatted on the
+/// stack to avoid allocating a String for every integer and length
+fn encode_integer(prefix: u8, value: i64, buf: &mut BytesMut) {
+    // 19 digits of i64::MIN plus the sign
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    let mut rest = value.unsigned_abs();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        start -= 1;
+        digits[start] = b'-';
+    }
+
+    buf.reserve(digits.len() - start + 3);
+    buf.put_u8(prefix);
+    buf.put(&digits[start..]);
     buf.put(&b"\r\n"[..]);
 }
 
@@ -131,29 +263,26 @@ impl Encoder for RespCodec {
     /// Encode a RespValue and push it to the buffer
     fn encode(&mut self, resp: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
         match resp {
-            RespValue::Null => {
-                buf.reserve(5);
-                buf.put(&b"$-1\r\n"[..]);
-            }
-            RespValue::SimpleString(value) => encode_string(b'+', value, buf),
+            RespValue::Null => buf.extend_from_slice(NULL),
+            RespValue::NullArray => buf.extend_from_slice(NULL_ARRAY),
+            RespValue::SimpleString(ref value) if value == "OK" => buf.extend_from_slice(OK),
+            RespValue::SimpleString(ref value) if value == "PONG" => buf.extend_from_slice(PONG),
+            RespValue::Integer(0) => buf.extend_from_slice(ZERO),
+            RespValue::Integer(1) => buf.extend_from_slice(ONE),
+            RespValue::Array(ref values) if values.is_empty() => buf.extend_from_slice(EMPTY_ARRAY),
+            RespValue::SimpleString(value) => encode_string(b'+', &value, buf),
             // TODO: support description
-            RespValue::Error(value, _description) => encode_string(b'-', value, buf),
-            RespValue::Integer(value) => encode_string(b':', value.to_string(), buf),
+            RespValue::Error(value, _description) => encode_string(b'-', &value, buf),
+            RespValue::Integer(value) => encode_integer(b':', value, buf),
             RespValue::BulkString(BulkString(value)) => {
-                let len_str = value.len().to_string();
-                buf.reserve(value.len() + len_str.len() + 5);
-                buf.put_u8(b'$');
-                buf.put(&len_str.into_bytes()[..]);
-                buf.put(&b"\r\n"[..]);
+                encode_integer(b'$', value.len() as i64, buf);
+                buf.reserve(value.len() + 2);
                 buf.put(&value[..]);
                 buf.put(&b"\r\n"[..]);
             }
             RespValue::Array(mut values) => {
-                let len_str = values.len().to_string();
-                buf.reserve(values.len() * 2 + len_str.len());
-                buf.put_u8(b'*');
-                buf.put(&len_str.into_bytes()[..]);
-                buf.put(&b"\r\n"[..]);
+                encode_integer(b'*', values.len() as i64, buf);
+                buf.reserve(values.len() * 2);
                 values.drain(..).for_each(|value| {
                     self.encode(value, buf).unwrap();
                 });
@@ -168,40 +297,28 @@ impl Decoder for RespCodec {
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        debug!("Decoding `{:?}`", str::from_utf8(src).unwrap_or("NOT UTF8"));
-
-        let (opt, removed_len) = combine::stream::decode(
-            resp_parser(),
-            &mut easy::Stream(PartialStream(&src[..])),
-            &mut self.state,
-        )
-        .map_err(|err| {
-            let err = err
-                .map_range(|r| {
-                    str::from_utf8(r)
-                        .ok()
-                        .map_or_else(|| format!("{:?}", r), |s| s.to_string())
-                })
-                .map_position(|p| p.translate_position(&src[..]));
-            format!("{}\nIn input: `{}`", err, str::from_utf8(src).unwrap())
-        })?;
-
-        debug!(
-            "Accepted {} bytes: `{:?}`",
-            removed_len,
-            str::from_utf8(&src[..removed_len]).unwrap_or("NOT UTF8")
-        );
+        // The conversions to log the input are expensive, only do them when they are logged
+        let log_debug = log_enabled!(Level::Debug);
+        if log_debug {
+            debug!("Decoding `{:?}`", str::from_utf8(src).unwrap_or("NOT UTF8"));
+        }
 
-        // Remove the input we just consumed.
-        // Ideally this would be done automatically by the call to
-        // `stream::decode` but it does unfortunately not work due
-        // to lifetime issues (Non lexical lifetimes might fix it!)
-        src.advance(removed_len);
+        let available = src.len();
+        let opt = if self.combine_parser {
+            self.decode_combine(src)?
+        } else {
+            self.parser.parse(src, self.inline_commands)?
+        };
 
         match opt {
             // `None` means we did not have enough input and we require that the
             // caller of `decode` supply more before calling us again
             None => {
+                // Don't let a client that never completes a frame make us buffer it forever
+                self.pending += available - src.len();
+                if self.pending + src.len() > self.query_buffer_limit {
+                    return Err("Protocol error: query buffer limit exceeded".into());
+                }
                 debug!("Requesting more input!");
                 Ok(None)
             }
@@ -209,7 +326,10 @@ impl Decoder for RespCodec {
             // `Some` means that a message was successfully decoded
             // (and that we are ready to start decoding the next message)
             Some(output) => {
-                debug!("Decoded `{:?}`", output);
+                self.pending = 0;
+                if log_debug {
+                    debug!("Decoded `{:?}`", output);
+                }
                 Ok(Some(output))
             }
         }
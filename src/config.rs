@@ -0,0 +1,156 @@
+use std::env;
+
+/// Server settings, they can be changed from the command line using the same syntax as
+/// redis-server, ie. `greenis --port 6380 --inline-commands no`
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    /// Accept telnet-style inline commands, when disabled only RESP arrays are accepted
+    pub inline_commands: bool,
+    /// Decode commands with the combine parser instead of the hand-written one, it's slower but
+    /// gives more detailed errors
+    pub combine_parser: bool,
+    /// Max bytes buffered for a command that is not complete yet, the client is disconnected
+    /// when it's exceeded
+    pub client_query_buffer_limit: usize,
+    /// Longest bulk string accepted from clients
+    pub proto_max_bulk_len: usize,
+    /// Size of the pending connections queue of the listener
+    pub tcp_backlog: i32,
+    /// Disable Nagle's algorithm on client connections
+    pub tcp_nodelay: bool,
+    /// Send buffer size of client sockets, 0 keeps the OS default
+    pub socket_sndbuf: usize,
+    /// Receive buffer size of client sockets, 0 keeps the OS default
+    pub socket_rcvbuf: usize,
+    /// Directory where the traffic of every connection is recorded, see `capture::Capture`
+    pub capture_dir: Option<String>,
+    /// Replay a capture file instead of starting the server
+    pub replay: Option<String>,
+    /// Cpus where the server threads are pinned, ie. `0-3,8`, empty to not pin them
+    pub server_cpulist: Vec<usize>,
+    /// Number of databases, selected with SELECT
+    pub databases: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: "127.0.0.1".into(),
+            port: 6142,
+            inline_commands: true,
+            combine_parser: false,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            tcp_backlog: 511,
+            tcp_nodelay: true,
+            socket_sndbuf: 0,
+            socket_rcvbuf: 0,
+            capture_dir: None,
+            replay: None,
+            server_cpulist: Vec::new(),
+            databases: 16,
+        }
+    }
+}
+
+impl Config {
+    /// Build the config from the process arguments
+    pub fn from_args() -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => return Err(format!("Invalid argument `{}`", arg)),
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for `{}`", arg))?;
+            config.set(name, &value)?;
+        }
+        Ok(config)
+    }
+
+    /// Change a setting by name
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_lowercase().as_ref() {
+            "bind" => self.bind = value.into(),
+            "port" => self.port = parse_number(name, value)?,
+            "inline-commands" => self.inline_commands = parse_bool(name, value)?,
+            "combine-parser" => self.combine_parser = parse_bool(name, value)?,
+            "client-query-buffer-limit" => {
+                self.client_query_buffer_limit = parse_number(name, value)?
+            }
+            "proto-max-bulk-len" => self.proto_max_bulk_len = parse_number(name, value)?,
+            "tcp-backlog" => self.tcp_backlog = parse_number(name, value)?,
+            "tcp-nodelay" => self.tcp_nodelay = parse_bool(name, value)?,
+            "socket-sndbuf" => self.socket_sndbuf = parse_number(name, value)?,
+            "socket-rcvbuf" => self.socket_rcvbuf = parse_number(name, value)?,
+            "capture-dir" => self.capture_dir = parse_optional(value),
+            "replay" => self.replay = parse_optional(value),
+            "server-cpulist" => self.server_cpulist = parse_cpulist(name, value)?,
+            "databases" => match parse_number(name, value)? {
+                0 => return Err(format!("`{}` must be at least 1", name)),
+                databases => self.databases = databases,
+            },
+            _ => return Err(format!("Unknown option `{}`", name)),
+        }
+        Ok(())
+    }
+
+    /// Address to listen for connections
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_ref() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("`{}` must be 'yes' or 'no', got `{}`", name, value)),
+    }
+}
+
+fn parse_optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.into())
+    }
+}
+
+/// Cpus that fit the cpu set of `sched_setaffinity`, `CPU_SETSIZE` in libc
+const MAX_CPUS: usize = 1024;
+
+/// Parse a list of cpus, ie. `0-3,8`
+fn parse_cpulist(name: &str, value: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for part in value.split(',').filter(|part| !part.is_empty()) {
+        let mut bounds = part.splitn(2, '-');
+        let start: usize = parse_number(name, bounds.next().unwrap_or_default())?;
+        let end = match bounds.next() {
+            Some(end) => parse_number(name, end)?,
+            None => start,
+        };
+        if end < start {
+            return Err(format!("`{}` has an invalid range `{}`", name, part));
+        }
+        if end >= MAX_CPUS {
+            return Err(format!(
+                "`{}` must have cpus below {}, got `{}`",
+                name, MAX_CPUS, part
+            ));
+        }
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` must be a number, got `{}`", name, value))
+}
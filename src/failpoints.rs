@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::types::RespValue;
+
+/// Fault injected when a command is received, to test how clients handle timeouts and errors
+/// Configured with `DEBUG FAILPOINT <command> DELAY <ms>|ERROR <message>|DROP|OFF`
+#[derive(Debug, Clone)]
+pub enum Failpoint {
+    /// Wait before running the command
+    Delay(Duration),
+    /// Reply with an error instead of running the command
+    Error(String),
+    /// Run the command but don't send the reply
+    Drop,
+}
+
+/// Active failpoints by command name
+static FAILPOINTS: Mutex<BTreeMap<String, Failpoint>> = Mutex::new(BTreeMap::new());
+
+impl Failpoint {
+    /// Parse the action of `DEBUG FAILPOINT`, `None` means the failpoint is disabled
+    pub fn parse(action: &str, arg: Option<String>) -> Result<Option<Failpoint>, &'static str> {
+        match (action.to_uppercase().as_ref(), arg) {
+            ("DELAY", Some(millis)) => match millis.parse() {
+                Ok(millis) => Ok(Some(Failpoint::Delay(Duration::from_millis(millis)))),
+                Err(_) => Err("ERR invalid delay"),
+            },
+            ("ERROR", Some(message)) => Ok(Some(Failpoint::Error(message))),
+            ("DROP", None) => Ok(Some(Failpoint::Drop)),
+            ("OFF", None) => Ok(None),
+            _ => Err("ERR syntax error"),
+        }
+    }
+}
+
+/// Enable or disable (with `None`) the failpoint of a command
+pub fn set(command: &str, failpoint: Option<Failpoint>) {
+    let mut failpoints = FAILPOINTS.lock().unwrap();
+    match failpoint {
+        Some(failpoint) => failpoints.insert(command.to_uppercase(), failpoint),
+        None => failpoints.remove(&command.to_uppercase()),
+    };
+}
+
+/// Failpoint of the command sent by the client, if any
+/// DEBUG is never affected, so failpoints can always be disabled
+pub fn for_command(resp: &RespValue) -> Option<Failpoint> {
+    let failpoints = FAILPOINTS.lock().unwrap();
+    if failpoints.is_empty() {
+        return None;
+    }
+    let name = match resp {
+        RespValue::Array(values) => match values.front() {
+            Some(RespValue::BulkString(name)) => name.to_string().to_uppercase(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if name == "DEBUG" {
+        return None;
+    }
+    failpoints.get(&name).cloned()
+}
@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+
+use crate::storage::Storage;
+use crate::types::{BulkString, GeoFrom, GeoSearchOptions, GeoShape, RedisKey, RespValue};
+use crate::value::{Score, SortedSet, Value};
+
+/// Bits of the geohash of each coordinate, 52 bits interleaved fit exactly in the score
+const STEP: u32 = 26;
+const LONGITUDE: (f64, f64) = (-180.0, 180.0);
+/// Limits of the web mercator projection, like redis
+const LATITUDE: (f64, f64) = (-85.051_128_78, 85.051_128_78);
+const EARTH_RADIUS: f64 = 6_372_797.560_856;
+
+/// Spread the 32 bits of `value` to the even bits of the result
+fn spread(value: u32) -> u64 {
+    let mut value = u64::from(value);
+    value = (value | value << 16) & 0x0000_ffff_0000_ffff;
+    value = (value | value << 8) & 0x00ff_00ff_00ff_00ff;
+    value = (value | value << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    value = (value | value << 2) & 0x3333_3333_3333_3333;
+    (value | value << 1) & 0x5555_5555_5555_5555
+}
+
+/// Gather the even bits of `value`, the inverse of `spread`
+fn squash(value: u64) -> u32 {
+    let mut value = value & 0x5555_5555_5555_5555;
+    value = (value | value >> 1) & 0x3333_3333_3333_3333;
+    value = (value | value >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    value = (value | value >> 4) & 0x00ff_00ff_00ff_00ff;
+    value = (value | value >> 8) & 0x0000_ffff_0000_ffff;
+    (value | value >> 16) as u32
+}
+
+/// Position of a coordinate in `2 ^ STEP` cells of its range
+fn cell((min, max): (f64, f64), value: f64) -> u32 {
+    ((value - min) / (max - min) * f64::from(1u32 << STEP)) as u32
+}
+
+/// Center of a cell of a coordinate, clamped to its range
+fn center((min, max): (f64, f64), cell: u32) -> f64 {
+    let size = (max - min) / f64::from(1u32 << STEP);
+    let center = min + (f64::from(cell) + 0.5) * size;
+    center.max(min).min(max)
+}
+
+/// Score of a position in a sorted set, the interleaved geohash of its coordinates with the
+/// latitude in the even bits, compatible with redis
+pub fn encode(longitude: f64, latitude: f64) -> Result<f64, &'static str> {
+    let valid = |(min, max): (f64, f64), value: f64| min <= value && value <= max;
+    if !valid(LONGITUDE, longitude) || !valid(LATITUDE, latitude) {
+        return Err("ERR invalid longitude,latitude pair");
+    }
+    let hash = spread(cell(LATITUDE, latitude)) | spread(cell(LONGITUDE, longitude)) << 1;
+    Ok(hash as f64)
+}
+
+/// Longitude and latitude of the center of the cell of a score
+pub fn decode(score: f64) -> (f64, f64) {
+    let hash = score as u64;
+    (
+        center(LONGITUDE, squash(hash >> 1)),
+        center(LATITUDE, squash(hash)),
+    )
+}
+
+/// Distance in meters between two latitudes of the same meridian
+fn latitude_distance(latitude1: f64, latitude2: f64) -> f64 {
+    EARTH_RADIUS * (latitude2.to_radians() - latitude1.to_radians()).abs()
+}
+
+/// Distance in meters between two positions, with the haversine formula
+pub fn distance((longitude1, latitude1): (f64, f64), (longitude2, latitude2): (f64, f64)) -> f64 {
+    let (latitude1, latitude2) = (latitude1.to_radians(), latitude2.to_radians());
+    let u = ((latitude2 - latitude1) / 2.0).sin();
+    let v = ((longitude2 - longitude1).to_radians() / 2.0).sin();
+    let a = u * u + latitude1.cos() * latitude2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Coordinate formatted as a reply
+fn coordinate_reply(value: f64) -> RespValue {
+    RespValue::BulkString(BulkString(value.to_string().into_bytes()))
+}
+
+/// Distance formatted as a reply, in `unit` meters with 4 decimals like redis
+pub fn distance_reply(meters: f64, unit: f64) -> RespValue {
+    RespValue::BulkString(BulkString(format!("{:.4}", meters / unit).into_bytes()))
+}
+
+/// Position of every member, or a null array for the ones that don't exist
+pub fn geopos(
+    storage: &mut Storage,
+    key: &RedisKey,
+    members: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get(key) {
+        Some(entry) => Some(entry.value.as_zset()?),
+        None => None,
+    };
+    let positions = members
+        .iter()
+        .map(|member| match zset.and_then(|zset| zset.get(member)) {
+            Some(score) => {
+                let (longitude, latitude) = decode(*score);
+                let position = vec![coordinate_reply(longitude), coordinate_reply(latitude)];
+                RespValue::Array(position.into())
+            }
+            None => RespValue::NullArray,
+        })
+        .collect::<VecDeque<_>>();
+    Ok(RespValue::Array(positions))
+}
+
+/// Distance between two members in `unit` meters, or null if one of them doesn't exist
+pub fn geodist(
+    storage: &mut Storage,
+    key: &RedisKey,
+    first: &BulkString,
+    second: &BulkString,
+    unit: f64,
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?,
+        None => return Ok(RespValue::Null),
+    };
+    match (zset.get(first), zset.get(second)) {
+        (Some(first), Some(second)) => {
+            let meters = distance(decode(*first), decode(*second));
+            Ok(distance_reply(meters, unit))
+        }
+        _ => Ok(RespValue::Null),
+    }
+}
+
+/// A member found by GEOSEARCH
+struct Found<'a> {
+    member: &'a BulkString,
+    score: f64,
+    /// Distance to the center in meters
+    distance: f64,
+}
+
+/// Members inside the shape of the search, sorted and limited by the options
+/// Without sorting they are in the order of their geohash
+fn search<'a>(
+    zset: &'a SortedSet,
+    options: &GeoSearchOptions,
+) -> Result<Vec<Found<'a>>, &'static str> {
+    let center = match &options.from {
+        GeoFrom::Member(member) => match zset.get(member) {
+            Some(score) => decode(*score),
+            None => return Err("ERR could not decode requested zset member"),
+        },
+        GeoFrom::LonLat(longitude, latitude) => (*longitude, *latitude),
+    };
+    let inside = |(longitude, latitude): (f64, f64)| match options.shape {
+        GeoShape::Radius(radius) => {
+            let distance = distance(center, (longitude, latitude));
+            Some(distance).filter(|distance| *distance <= radius)
+        }
+        GeoShape::Box(width, height) => {
+            // Compared along the meridian and the parallel of the position, like redis
+            if latitude_distance(latitude, center.1) > height / 2.0
+                || distance((longitude, latitude), (center.0, latitude)) > width / 2.0
+            {
+                return None;
+            }
+            Some(distance(center, (longitude, latitude)))
+        }
+    };
+
+    let limit = options.count.unwrap_or(usize::MAX);
+    let mut found = Vec::new();
+    for (Score(score), member) in zset.ordered() {
+        if let Some(distance) = inside(decode(*score)) {
+            found.push(Found {
+                member,
+                score: *score,
+                distance,
+            });
+            if options.any && found.len() == limit {
+                break;
+            }
+        }
+    }
+    // The closest ones are the first with COUNT
+    if options.desc {
+        found.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+    } else if options.asc || (options.count.is_some() && !options.any) {
+        found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    }
+    found.truncate(limit);
+    Ok(found)
+}
+
+/// Members of a sorted set of positions inside an area, with their distance, geohash and
+/// position when asked
+pub fn geosearch(
+    storage: &mut Storage,
+    key: &RedisKey,
+    options: &GeoSearchOptions,
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?,
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    let with_any = options.with_dist || options.with_hash || options.with_coord;
+    let found = search(zset, options)?
+        .into_iter()
+        .map(|found| {
+            let member = RespValue::BulkString(found.member.clone());
+            if !with_any {
+                return member;
+            }
+            let mut item = vec![member];
+            if options.with_dist {
+                item.push(distance_reply(found.distance, options.unit));
+            }
+            if options.with_hash {
+                item.push(RespValue::Integer(found.score as i64));
+            }
+            if options.with_coord {
+                let (longitude, latitude) = decode(found.score);
+                let position = vec![coordinate_reply(longitude), coordinate_reply(latitude)];
+                item.push(RespValue::Array(position.into()));
+            }
+            RespValue::Array(item.into())
+        })
+        .collect();
+    Ok(RespValue::Array(found))
+}
+
+/// Store the members found by a search in `destination`, with their distance as the score
+/// with STOREDIST. Replies the number of members stored, the destination is removed if there are
+/// none
+pub fn geosearchstore(
+    storage: &mut Storage,
+    destination: &RedisKey,
+    key: &RedisKey,
+    options: &GeoSearchOptions,
+) -> Result<RespValue, &'static str> {
+    let mut stored = SortedSet::default();
+    if let Some(entry) = storage.get(key) {
+        for found in search(entry.value.as_zset()?, options)? {
+            let score = if options.store_dist {
+                found.distance / options.unit
+            } else {
+                found.score
+            };
+            stored.insert(found.member.clone(), score);
+        }
+    }
+    let len = stored.len();
+    if stored.is_empty() {
+        storage.remove(destination);
+    } else {
+        storage.set(destination.clone(), Value::ZSet(stored));
+    }
+    Ok(RespValue::Integer(len as i64))
+}
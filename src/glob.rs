@@ -0,0 +1,130 @@
+/// Glob-style pattern matching of MATCH options, like redis `stringmatchlen`
+/// `*` matches any sequence, `?` any byte, `[abc]`, `[^abc]` and `[a-z]` sets of bytes, and `\`
+/// escapes the next byte
+/// Iterative with a single backtrack point at the last star, so it's linear in the stack and at
+/// most quadratic in time
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Pattern after the last star and the position of the string it's being matched from
+    let mut star = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(len) = match_byte(&pattern[p..], string[s]) {
+            p += len;
+            s += 1;
+            continue;
+        }
+        // The last star takes one more byte and the rest of the pattern is tried again
+        match star {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                star = Some((star_p, s));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Match a byte against the element at the start of `pattern`, which is not a star
+/// Returns the length of the element if it matched
+fn match_byte(pattern: &[u8], byte: u8) -> Option<usize> {
+    let (matched, len) = match pattern {
+        [] => return None,
+        [b'?', ..] => (true, 1),
+        [b'[', rest @ ..] => {
+            let (matched, rest) = match_set(rest, byte);
+            (matched, pattern.len() - rest.len())
+        }
+        [b'\\', escaped, ..] => (*escaped == byte, 2),
+        [other, ..] => (*other == byte, 1),
+    };
+    Some(len).filter(|_| matched)
+}
+
+/// Match a byte against the set at the start of `pattern`, after the `[`
+/// Returns if it matched and the pattern after the closing `]`
+fn match_set(pattern: &[u8], byte: u8) -> (bool, &[u8]) {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    loop {
+        match pattern {
+            // An unclosed set ends with the pattern
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end {
+                    (*start, *end)
+                } else {
+                    (*end, *start)
+                };
+                matched |= low <= byte && byte <= high;
+                pattern = rest;
+            }
+            [other, rest @ ..] => {
+                matched |= *other == byte;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, string: &str) -> bool {
+        matches(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert!(glob("*", ""));
+        assert!(glob("h?llo", "hello"));
+        assert!(!glob("h?llo", "hllo"));
+        assert!(glob("*a*b", "xxaxxab"));
+        assert!(!glob("*a*b", "xxaxxa"));
+        assert!(glob("a**", "a"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob("a\\*", "a*"));
+        assert!(!glob("a\\*", "ab"));
+        assert!(glob("\\?", "?"));
+        assert!(!glob("\\?", "x"));
+        assert!(glob("[\\]]", "]"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(glob("h[ae]llo", "hallo"));
+        assert!(!glob("h[ae]llo", "hillo"));
+        assert!(glob("h[^e]llo", "hallo"));
+        assert!(!glob("h[^e]llo", "hello"));
+        assert!(glob("[a-c]", "b"));
+        assert!(!glob("[a-c]", "d"));
+        // Reversed ranges are swapped, like redis
+        assert!(glob("[c-a]", "b"));
+        assert!(glob("[a-]", "-"));
+        // An unclosed set ends with the pattern
+        assert!(glob("[ab", "a"));
+        assert!(!glob("[ab", "ab"));
+    }
+}
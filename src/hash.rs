@@ -0,0 +1,347 @@
+use rand::seq::IteratorRandom;
+use std::collections::VecDeque;
+
+use crate::glob;
+use crate::random;
+use crate::storage::{now_ms, Storage};
+use crate::types::{BulkString, ExpireOptions, RedisKey, RespValue, ScanOptions};
+use crate::value::{Hash, Value};
+
+/// Set fields of a hash, creating it if needed, replies the number of new fields
+pub fn hset(
+    storage: &mut Storage,
+    key: &RedisKey,
+    pairs: Vec<(BulkString, BulkString)>,
+) -> Result<RespValue, &'static str> {
+    let hash = storage
+        .get_or_insert_with(key, || Value::Hash(Hash::default()))
+        .as_hash_mut()?;
+    let mut created = 0;
+    for (field, value) in pairs {
+        if hash.insert(field, value).is_none() {
+            created += 1;
+        }
+    }
+    Ok(RespValue::Integer(created))
+}
+
+/// Set a field only if it doesn't exist yet, replies 1 if it was set
+pub fn hsetnx(
+    storage: &mut Storage,
+    key: &RedisKey,
+    field: &BulkString,
+    value: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let hash = storage
+        .get_or_insert_with(key, || Value::Hash(Hash::default()))
+        .as_hash_mut()?;
+    if hash.contains_key(field) {
+        return Ok(RespValue::Integer(0));
+    }
+    hash.insert(field.clone(), value.clone());
+    Ok(RespValue::Integer(1))
+}
+
+pub fn hget(
+    storage: &mut Storage,
+    key: &RedisKey,
+    field: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let value = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?.get(field),
+        None => None,
+    };
+    Ok(value.map_or(RespValue::Null, |value| {
+        RespValue::BulkString(value.clone())
+    }))
+}
+
+/// Length of the value of a field, 0 if it doesn't exist
+pub fn hstrlen(
+    storage: &mut Storage,
+    key: &RedisKey,
+    field: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry
+            .value
+            .as_hash()?
+            .get(field)
+            .map_or(0, |value| value.0.len()),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Values of several fields, null for the missing ones
+pub fn hmget(
+    storage: &mut Storage,
+    key: &RedisKey,
+    fields: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => Some(entry.value.as_hash()?),
+        None => None,
+    };
+    Ok(RespValue::Array(
+        fields
+            .iter()
+            .map(|field| match hash.and_then(|hash| hash.get(field)) {
+                Some(value) => RespValue::BulkString(value.clone()),
+                None => RespValue::Null,
+            })
+            .collect(),
+    ))
+}
+
+/// Remove fields of a hash, the key is removed with the last field
+pub fn hdel(
+    storage: &mut Storage,
+    key: &RedisKey,
+    fields: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_hash_mut()?,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let removed = fields
+        .iter()
+        .filter(|field| hash.remove(field).is_some())
+        .count();
+    storage.remove_if_empty(key);
+    Ok(RespValue::Integer(removed as i64))
+}
+
+/// All the fields and values of a hash, flattened in a single array
+pub fn hgetall(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None => return Ok(RespValue::Array(Default::default())),
+    };
+    Ok(RespValue::Array(
+        hash.iter()
+            .flat_map(|(field, value)| {
+                vec![
+                    RespValue::BulkString(field.clone()),
+                    RespValue::BulkString(value.clone()),
+                ]
+            })
+            .collect(),
+    ))
+}
+
+/// Fields of a hash
+pub fn hkeys(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None => return Ok(RespValue::Array(Default::default())),
+    };
+    Ok(RespValue::Array(
+        hash.keys()
+            .map(|field| RespValue::BulkString(field.clone()))
+            .collect(),
+    ))
+}
+
+/// Values of a hash
+pub fn hvals(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None => return Ok(RespValue::Array(Default::default())),
+    };
+    Ok(RespValue::Array(
+        hash.values()
+            .map(|value| RespValue::BulkString(value.clone()))
+            .collect(),
+    ))
+}
+
+/// Number of fields of a hash
+pub fn hlen(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?.len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+pub fn hexists(
+    storage: &mut Storage,
+    key: &RedisKey,
+    field: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let exists = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?.contains_key(field),
+        None => false,
+    };
+    Ok(RespValue::Integer(exists.into()))
+}
+
+/// Iterate a hash a few fields at a time
+/// Fields are visited in the order of their scan position and the cursor is the position of the
+/// next one, so the fields that exist during the whole iteration are replied even if the hash
+/// changes between calls, and every call resumes in O(COUNT)
+pub fn hscan(
+    storage: &mut Storage,
+    key: &RedisKey,
+    cursor: u64,
+    options: &ScanOptions,
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None => return Ok(scan_reply(0, VecDeque::new())),
+    };
+    let mut fields = hash.scan(cursor);
+    let mut elements = VecDeque::new();
+    for (_, field) in fields.by_ref().take(options.count) {
+        if let Some(pattern) = &options.pattern {
+            if !glob::matches(&pattern.0, &field.0) {
+                continue;
+            }
+        }
+        elements.push_back(RespValue::BulkString(field.clone()));
+        if !options.no_values {
+            elements.push_back(RespValue::BulkString(hash[field].clone()));
+        }
+    }
+    let next = fields.next().map_or(0, |(position, _)| position);
+    Ok(scan_reply(next, elements))
+}
+
+/// Next cursor, 0 when the iteration is complete, and the elements found
+fn scan_reply(cursor: u64, elements: VecDeque<RespValue>) -> RespValue {
+    RespValue::Array(
+        vec![
+            RespValue::BulkString(BulkString(cursor.to_string().into_bytes())),
+            RespValue::Array(elements),
+        ]
+        .into(),
+    )
+}
+
+/// Random fields of a hash
+/// Without count a single field is replied, or null. A positive count replies distinct fields,
+/// up to the size of the hash, and a negative count replies that many fields, maybe repeated
+pub fn hrandfield(
+    storage: &mut Storage,
+    key: &RedisKey,
+    count: Option<i64>,
+    with_values: bool,
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None if count.is_none() => return Ok(RespValue::Null),
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    let mut rng = rand::thread_rng();
+    let count = match count {
+        Some(count) => count,
+        None => {
+            return Ok(hash
+                .keys()
+                .choose(&mut rng)
+                .map_or(RespValue::Null, |field| {
+                    RespValue::BulkString(field.clone())
+                }))
+        }
+    };
+
+    let mut elements = VecDeque::new();
+    for (field, value) in random::sample(hash.iter(), count) {
+        elements.push_back(RespValue::BulkString(field.clone()));
+        if with_values {
+            elements.push_back(RespValue::BulkString(value.clone()));
+        }
+    }
+    Ok(RespValue::Array(elements))
+}
+
+/// Change the expiration of fields of a hash, replies for every field -2 if it doesn't exist, 0
+/// if the condition is not met, 1 if the expiration was changed and 2 if the field was removed
+/// because the time is in the past
+pub fn hexpire(
+    storage: &mut Storage,
+    key: &RedisKey,
+    expires_at: i64,
+    options: &ExpireOptions,
+    fields: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_hash_mut()?,
+        None => return Ok(integers(fields.iter().map(|_| -2))),
+    };
+    let now = now_ms();
+    let replies: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            if !hash.contains_key(field) {
+                -2
+            } else if !options.allows(hash.expiration(field), expires_at) {
+                0
+            } else if expires_at <= now {
+                hash.remove(field);
+                2
+            } else {
+                hash.set_expiration(field, Some(expires_at));
+                1
+            }
+        })
+        .collect();
+    storage.remove_if_empty(key);
+    storage.track_field_expirations(key);
+    Ok(integers(replies.into_iter()))
+}
+
+/// Time to live of fields of a hash, -2 if the field doesn't exist and -1 if it has no expiration
+pub fn httl(
+    storage: &mut Storage,
+    key: &RedisKey,
+    fields: &[BulkString],
+    millis: bool,
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.peek(key) {
+        Some(entry) => entry.value.as_hash()?,
+        None => return Ok(integers(fields.iter().map(|_| -2))),
+    };
+    let now = now_ms();
+    Ok(integers(fields.iter().map(|field| {
+        match (hash.contains_key(field), hash.expiration(field)) {
+            (false, _) => -2,
+            (true, None) => -1,
+            (true, Some(expires_at)) if millis => (expires_at - now).max(0),
+            // Rounded to the closest second, like TTL
+            (true, Some(expires_at)) => ((expires_at - now).max(0) + 500) / 1000,
+        }
+    })))
+}
+
+/// Remove the expiration of fields of a hash, replies for every field -2 if it doesn't exist,
+/// -1 if it has no expiration and 1 if the expiration was removed
+pub fn hpersist(
+    storage: &mut Storage,
+    key: &RedisKey,
+    fields: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let hash = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_hash_mut()?,
+        None => return Ok(integers(fields.iter().map(|_| -2))),
+    };
+    let replies: Vec<_> = fields
+        .iter()
+        .map(
+            |field| match (hash.contains_key(field), hash.expiration(field)) {
+                (false, _) => -2,
+                (true, None) => -1,
+                (true, Some(_)) => {
+                    hash.set_expiration(field, None);
+                    1
+                }
+            },
+        )
+        .collect();
+    Ok(integers(replies.into_iter()))
+}
+
+fn integers(values: impl Iterator<Item = i64>) -> RespValue {
+    RespValue::Array(values.map(RespValue::Integer).collect())
+}
@@ -0,0 +1,293 @@
+use crate::storage::Storage;
+use crate::types::{BulkString, RedisKey, RespValue};
+use crate::value::Value;
+
+/// Bits of the hash used to pick a register
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits of the hash left to count the leading zeros
+const Q: u32 = 64 - P;
+/// Bits of every register
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+/// Magic, encoding, 3 unused bytes and the cached cardinality, like redis
+const HEADER_LEN: usize = 16;
+/// Header and the registers in dense encoding
+const DENSE_LEN: usize = HEADER_LEN + REGISTERS * REGISTER_BITS / 8;
+const MAGIC: &[u8] = b"HYLL";
+const DENSE: u8 = 0;
+/// The most significant bit of the cached cardinality marks it as invalid
+const STALE: u8 = 1 << 7;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// Error replied when a string is not a HyperLogLog, sparse ones created by redis included
+const INVALID: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+
+/// MurmurHash64A, the hash used by redis so the same elements set the same registers
+fn murmur_hash(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut hash = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes([
+            chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+        ]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        hash ^= k;
+        hash = hash.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (position, byte) in tail.iter().enumerate() {
+            hash ^= u64::from(*byte) << (8 * position);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+    hash ^= hash >> R;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> R;
+    hash
+}
+
+/// Register of an element and the value it would set, the position of the first set bit of
+/// the rest of its hash
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash(element, 0xadc8_3b19);
+    let index = hash as usize & (REGISTERS - 1);
+    // The extra bit makes the count stop at Q + 1
+    let rest = hash >> P | 1 << Q;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// A new empty HyperLogLog, its cached cardinality is 0
+fn new_hll() -> Vec<u8> {
+    let mut hll = vec![0; DENSE_LEN];
+    hll[..MAGIC.len()].copy_from_slice(MAGIC);
+    hll[MAGIC.len()] = DENSE;
+    hll
+}
+
+/// The string of a value if it's a HyperLogLog in dense encoding
+fn as_hll(value: &Value) -> Result<&Vec<u8>, &'static str> {
+    let hll = &value.as_string()?.0;
+    if hll.len() != DENSE_LEN || !hll.starts_with(MAGIC) || hll[MAGIC.len()] != DENSE {
+        return Err(INVALID);
+    }
+    Ok(hll)
+}
+
+fn as_hll_mut(value: &mut Value) -> Result<&mut Vec<u8>, &'static str> {
+    as_hll(value)?;
+    Ok(&mut value.as_string_mut()?.0)
+}
+
+/// Registers are packed in 6 bits starting from the least significant bit of every byte, some of
+/// them continue in the next byte
+fn register(hll: &[u8], index: usize) -> u8 {
+    let byte = HEADER_LEN + index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    let mut value = hll[byte] >> shift;
+    if shift + REGISTER_BITS > 8 {
+        value |= hll[byte + 1] << (8 - shift);
+    }
+    value & REGISTER_MAX
+}
+
+fn set_register(hll: &mut [u8], index: usize, value: u8) {
+    let byte = HEADER_LEN + index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    hll[byte] &= !(REGISTER_MAX << shift);
+    hll[byte] |= value << shift;
+    if shift + REGISTER_BITS > 8 {
+        hll[byte + 1] &= !(REGISTER_MAX >> (8 - shift));
+        hll[byte + 1] |= value >> (8 - shift);
+    }
+}
+
+/// Cardinality cached in the header, `None` if a register changed since it was computed
+fn cached(hll: &[u8]) -> Option<u64> {
+    if hll[HEADER_LEN - 1] & STALE != 0 {
+        return None;
+    }
+    let mut cardinality = [0; 8];
+    cardinality.copy_from_slice(&hll[8..HEADER_LEN]);
+    Some(u64::from_le_bytes(cardinality))
+}
+
+fn set_cached(hll: &mut [u8], cardinality: Option<u64>) {
+    match cardinality {
+        Some(cardinality) => hll[8..HEADER_LEN].copy_from_slice(&cardinality.to_le_bytes()),
+        None => hll[HEADER_LEN - 1] |= STALE,
+    }
+}
+
+/// Estimate the cardinality from the values of the registers, with the improved estimator of
+/// Otmar Ertl used by redis
+fn estimate(registers: impl Iterator<Item = u8>) -> u64 {
+    let mut histogram = [0u32; 64];
+    for value in registers {
+        histogram[value as usize] += 1;
+    }
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - f64::from(histogram[Q as usize + 1])) / m);
+    for count in histogram[1..=Q as usize].iter().rev() {
+        z += f64::from(*count);
+        z *= 0.5;
+    }
+    z += m * sigma(f64::from(histogram[0]) / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Add elements to a HyperLogLog, creating it if needed
+/// Replies 1 if it was created or a register changed, so the estimation may be different
+pub fn pfadd(
+    storage: &mut Storage,
+    key: &RedisKey,
+    elements: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let mut changed = storage.get(key).is_none();
+    let hll = as_hll_mut(storage.get_or_insert_with(key, || Value::String(BulkString(new_hll()))))?;
+    for element in elements {
+        let (index, value) = pattern(&element.0);
+        if register(hll, index) < value {
+            set_register(hll, index, value);
+            set_cached(hll, None);
+            changed = true;
+        }
+    }
+    Ok(RespValue::Integer(changed as i64))
+}
+
+/// Estimated cardinality of a HyperLogLog, or of the union of several of them
+/// The estimation of a single key is cached in its header until it changes
+pub fn pfcount(storage: &mut Storage, keys: &[RedisKey]) -> Result<RespValue, &'static str> {
+    if let [key] = keys {
+        let hll = match storage.get_mut(key) {
+            Some(entry) => as_hll_mut(&mut entry.value)?,
+            None => return Ok(RespValue::Integer(0)),
+        };
+        let cardinality = match cached(hll) {
+            Some(cardinality) => cardinality,
+            None => {
+                let cardinality = estimate((0..REGISTERS).map(|index| register(hll, index)));
+                set_cached(hll, Some(cardinality));
+                cardinality
+            }
+        };
+        return Ok(RespValue::Integer(cardinality as i64));
+    }
+    let registers = merge(storage, keys)?;
+    Ok(RespValue::Integer(estimate(registers.into_iter()) as i64))
+}
+
+/// Highest value of every register in several HyperLogLogs, missing keys are skipped
+fn merge(storage: &mut Storage, keys: &[RedisKey]) -> Result<Vec<u8>, &'static str> {
+    let mut registers = vec![0; REGISTERS];
+    for entry in storage.get_many(keys).into_iter().flatten() {
+        let hll = as_hll(&entry.value)?;
+        for (index, value) in registers.iter_mut().enumerate() {
+            *value = (*value).max(register(hll, index));
+        }
+    }
+    Ok(registers)
+}
+
+/// Merge several HyperLogLogs into `destination`, which is part of the union if it exists
+pub fn pfmerge(
+    storage: &mut Storage,
+    destination: &RedisKey,
+    keys: &[RedisKey],
+) -> Result<RespValue, &'static str> {
+    let mut sources = vec![destination.clone()];
+    sources.extend_from_slice(keys);
+    let registers = merge(storage, &sources)?;
+    let hll = as_hll_mut(
+        storage.get_or_insert_with(destination, || Value::String(BulkString(new_hll()))),
+    )?;
+    for (index, value) in registers.into_iter().enumerate() {
+        set_register(hll, index, value);
+    }
+    set_cached(hll, None);
+    Ok(RespValue::SimpleString("OK".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(storage: &mut Storage, keys: &[RedisKey]) -> i64 {
+        match pfcount(storage, keys).unwrap() {
+            RespValue::Integer(count) => count,
+            _ => panic!("PFCOUNT should reply an integer"),
+        }
+    }
+
+    #[test]
+    fn pfcount_known_cardinality() {
+        let mut storage = Storage::new();
+        let keys = [
+            BulkString(b"first".to_vec()),
+            BulkString(b"second".to_vec()),
+        ];
+        let elements: Vec<_> = (0..100_000)
+            .map(|index| BulkString(format!("element:{}", index).into_bytes()))
+            .collect();
+        pfadd(&mut storage, &keys[0], &elements[..50_000]).unwrap();
+        pfadd(&mut storage, &keys[1], &elements[25_000..]).unwrap();
+        // The standard error is 0.81%, allow 3 of it
+        let within = |count: i64, expected: f64| (count as f64 - expected).abs() < expected * 0.025;
+        assert!(within(count(&mut storage, &keys[..1]), 50_000.0));
+        // Served from the cache the second time
+        assert!(within(count(&mut storage, &keys[..1]), 50_000.0));
+        assert!(within(count(&mut storage, &keys[1..]), 75_000.0));
+        assert!(within(count(&mut storage, &keys), 100_000.0));
+    }
+
+    #[test]
+    fn pfcount_small_cardinality() {
+        let mut storage = Storage::new();
+        let keys = [BulkString(b"key".to_vec())];
+        assert_eq!(count(&mut storage, &keys), 0);
+        let elements: Vec<_> = (0..10).map(|index| BulkString(vec![index])).collect();
+        pfadd(&mut storage, &keys[0], &elements).unwrap();
+        pfadd(&mut storage, &keys[0], &elements).unwrap();
+        assert_eq!(count(&mut storage, &keys), 10);
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::VecDeque;
+
+use crate::storage::Storage;
+use crate::types::{BulkString, LPosOptions, RedisKey, RespValue};
+use crate::value::Value;
+
+/// End of a list where elements are pushed or popped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    fn push(self, list: &mut VecDeque<BulkString>, element: BulkString) {
+        match self {
+            Side::Left => list.push_front(element),
+            Side::Right => list.push_back(element),
+        }
+    }
+
+    fn pop(self, list: &mut VecDeque<BulkString>) -> Option<BulkString> {
+        match self {
+            Side::Left => list.pop_front(),
+            Side::Right => list.pop_back(),
+        }
+    }
+
+    /// Pop up to `count` elements, in the order they are popped
+    fn pop_many(self, list: &mut VecDeque<BulkString>, count: usize) -> VecDeque<RespValue> {
+        (0..count.min(list.len()))
+            .filter_map(|_| self.pop(list))
+            .map(RespValue::BulkString)
+            .collect()
+    }
+}
+
+/// Push elements one after the other, so pushing `a b` to the left leaves `b a` at the head
+/// With `existing` only lists that already exist are pushed to
+/// Replies the length of the list after the push
+pub fn push(
+    storage: &mut Storage,
+    key: &RedisKey,
+    side: Side,
+    existing: bool,
+    elements: Vec<BulkString>,
+) -> Result<RespValue, &'static str> {
+    let list = if existing {
+        match storage.get_mut(key) {
+            Some(entry) => entry.value.as_list_mut()?,
+            None => return Ok(RespValue::Integer(0)),
+        }
+    } else {
+        storage
+            .get_or_insert_with(key, || Value::List(VecDeque::new()))
+            .as_list_mut()?
+    };
+    for element in elements {
+        side.push(list, element);
+    }
+    Ok(RespValue::Integer(list.len() as i64))
+}
+
+/// Remove and reply an element from one end, the key is removed with the last element
+/// With a count up to that many elements are replied in an array
+pub fn pop(
+    storage: &mut Storage,
+    key: &RedisKey,
+    side: Side,
+    count: Option<usize>,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_list_mut()?,
+        None if count.is_some() => return Ok(RespValue::NullArray),
+        None => return Ok(RespValue::Null),
+    };
+    let reply = match count {
+        Some(count) => RespValue::Array(side.pop_many(list, count)),
+        None => side
+            .pop(list)
+            .map_or(RespValue::Null, RespValue::BulkString),
+    };
+    storage.remove_if_empty(key);
+    Ok(reply)
+}
+
+/// Pop an element from `source` and push it to `destination`, they can be the same list
+/// Replies the element moved, or null if the source doesn't exist
+pub fn lmove(
+    storage: &mut Storage,
+    source: &RedisKey,
+    destination: &RedisKey,
+    from: Side,
+    to: Side,
+) -> Result<RespValue, &'static str> {
+    match storage.get(source) {
+        Some(entry) => entry.value.as_list()?,
+        None => return Ok(RespValue::Null),
+    };
+    // Check the type of the destination before changing anything
+    if let Some(entry) = storage.get(destination) {
+        entry.value.as_list()?;
+    }
+    let element = match storage.get_mut(source) {
+        Some(entry) => from.pop(entry.value.as_list_mut()?),
+        None => None,
+    };
+    let element = match element {
+        Some(element) => element,
+        None => return Ok(RespValue::Null),
+    };
+    let list = storage
+        .get_or_insert_with(destination, || Value::List(VecDeque::new()))
+        .as_list_mut()?;
+    to.push(list, element.clone());
+    // After the push, so moving the only element of a list to itself keeps the key
+    storage.remove_if_empty(source);
+    Ok(RespValue::BulkString(element))
+}
+
+/// Pop from the first list that isn't empty, replies the key and the element, or null if
+/// all of them are empty
+pub fn bpop(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    side: Side,
+) -> Result<RespValue, &'static str> {
+    for key in keys {
+        if let RespValue::BulkString(element) = pop(storage, key, side, None)? {
+            let reply = vec![
+                RespValue::BulkString(key.clone()),
+                RespValue::BulkString(element),
+            ];
+            return Ok(RespValue::Array(reply.into()));
+        }
+    }
+    Ok(RespValue::Null)
+}
+
+/// Pop up to `count` elements from the first list that isn't empty
+/// Replies the key and the elements, or a null array if all of them are empty
+pub fn lmpop(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    side: Side,
+    count: usize,
+) -> Result<RespValue, &'static str> {
+    for key in keys {
+        let list = match storage.get_mut(key) {
+            Some(entry) => entry.value.as_list_mut()?,
+            None => continue,
+        };
+        let elements = side.pop_many(list, count);
+        storage.remove_if_empty(key);
+        let reply = vec![
+            RespValue::BulkString(key.clone()),
+            RespValue::Array(elements),
+        ];
+        return Ok(RespValue::Array(reply.into()));
+    }
+    Ok(RespValue::NullArray)
+}
+
+/// Number of elements of a list
+pub fn llen(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry.value.as_list()?.len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Elements between two inclusive indexes, negative ones start from the end
+pub fn lrange(
+    storage: &mut Storage,
+    key: &RedisKey,
+    start: i64,
+    end: i64,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get(key) {
+        Some(entry) => entry.value.as_list()?,
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    let elements = match range(list.len(), start, end) {
+        Some((start, end)) => list
+            .range(start..=end)
+            .map(|element| RespValue::BulkString(element.clone()))
+            .collect(),
+        None => VecDeque::new(),
+    };
+    Ok(RespValue::Array(elements))
+}
+
+/// Element at an index, negative ones start from the end
+pub fn lindex(
+    storage: &mut Storage,
+    key: &RedisKey,
+    index: i64,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get(key) {
+        Some(entry) => entry.value.as_list()?,
+        None => return Ok(RespValue::Null),
+    };
+    let element = position(list.len(), index).and_then(|index| list.get(index));
+    Ok(element.map_or(RespValue::Null, |element| {
+        RespValue::BulkString(element.clone())
+    }))
+}
+
+/// Replace the element at an index, the list must exist and be long enough
+pub fn lset(
+    storage: &mut Storage,
+    key: &RedisKey,
+    index: i64,
+    element: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_list_mut()?,
+        None => return Err("ERR no such key"),
+    };
+    match position(list.len(), index).and_then(|index| list.get_mut(index)) {
+        Some(current) => *current = element.clone(),
+        None => return Err("ERR index out of range"),
+    }
+    Ok(RespValue::SimpleString("OK".into()))
+}
+
+/// Positions of an element, the first match after skipping `rank - 1` of them
+/// Replies a single position or null, or an array of them with the COUNT option
+pub fn lpos(
+    storage: &mut Storage,
+    key: &RedisKey,
+    element: &BulkString,
+    options: &LPosOptions,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get(key) {
+        Some(entry) => Some(entry.value.as_list()?),
+        None => None,
+    };
+    let max_len = match options.max_len {
+        0 => usize::MAX,
+        max_len => max_len,
+    };
+    let count = match options.count {
+        Some(0) => usize::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    let skip = (options.rank.unsigned_abs() - 1) as usize;
+    let positions: VecDeque<_> = match list {
+        Some(list) => {
+            let indexes: Box<dyn Iterator<Item = usize>> = if options.rank > 0 {
+                Box::new(0..list.len())
+            } else {
+                Box::new((0..list.len()).rev())
+            };
+            indexes
+                .take(max_len)
+                .filter(|&index| &list[index] == element)
+                .skip(skip)
+                .take(count)
+                .map(|index| RespValue::Integer(index as i64))
+                .collect()
+        }
+        None => VecDeque::new(),
+    };
+    Ok(match options.count {
+        Some(_) => RespValue::Array(positions),
+        None => positions.into_iter().next().unwrap_or(RespValue::Null),
+    })
+}
+
+/// Insert an element before or after the first occurrence of `pivot`
+/// Replies the new length, -1 if the pivot wasn't found and 0 if the list doesn't exist
+pub fn linsert(
+    storage: &mut Storage,
+    key: &RedisKey,
+    before: bool,
+    pivot: &BulkString,
+    element: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_list_mut()?,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let index = match list.iter().position(|current| current == pivot) {
+        Some(index) if before => index,
+        Some(index) => index + 1,
+        None => return Ok(RespValue::Integer(-1)),
+    };
+    list.insert(index, element.clone());
+    Ok(RespValue::Integer(list.len() as i64))
+}
+
+/// Remove the first `count` occurrences of an element, from the tail if it's negative and all
+/// of them if it's 0
+/// Replies how many were removed
+pub fn lrem(
+    storage: &mut Storage,
+    key: &RedisKey,
+    count: i64,
+    element: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_list_mut()?,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let limit = match count {
+        0 => usize::MAX,
+        _ => count.unsigned_abs() as usize,
+    };
+    let mut removed = 0;
+    if count < 0 {
+        let mut index = list.len();
+        while index > 0 && removed < limit {
+            index -= 1;
+            if &list[index] == element {
+                list.remove(index);
+                removed += 1;
+            }
+        }
+    } else {
+        list.retain(|current| {
+            if removed < limit && current == element {
+                removed += 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+    storage.remove_if_empty(key);
+    Ok(RespValue::Integer(removed as i64))
+}
+
+/// Keep only the elements between two inclusive indexes, like LRANGE
+pub fn ltrim(
+    storage: &mut Storage,
+    key: &RedisKey,
+    start: i64,
+    end: i64,
+) -> Result<RespValue, &'static str> {
+    let list = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_list_mut()?,
+        None => return Ok(RespValue::SimpleString("OK".into())),
+    };
+    match range(list.len(), start, end) {
+        Some((start, end)) => {
+            list.truncate(end + 1);
+            list.drain(..start);
+        }
+        None => list.clear(),
+    }
+    storage.remove_if_empty(key);
+    Ok(RespValue::SimpleString("OK".into()))
+}
+
+/// Resolve a redis index of `len` elements to a position, `None` if it's out of range
+fn position(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+/// Resolve a redis range of `len` elements to inclusive positions, `None` if it's empty
+/// Negative indexes start from the end, then the range is clamped to the elements
+pub fn range(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.min(len - 1);
+    if start > end {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
@@ -1,84 +1,497 @@
+mod bitmap;
+mod capture;
+mod client;
 mod codec;
+mod config;
+#[cfg(feature = "failpoints")]
+mod failpoints;
+mod geo;
+mod glob;
+mod hash;
+mod hyperloglog;
+mod list;
+mod parser;
+mod random;
+mod rdb;
+mod set;
+mod stats;
+mod storage;
+mod stream;
 mod types;
+mod value;
+mod zset;
 
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::sync::{Arc, Mutex};
+use std::fs::File;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use futures::stream::StreamExt;
-use tokio::net::TcpListener;
+use net2::TcpBuilder;
+use tokio::net::{TcpListener, TcpStream};
 
 use futures::prelude::*;
 use tokio;
 use tokio_util::codec::Framed;
 
+use capture::{Capture, Replay};
+use client::Client;
 use codec::RespCodec;
-use types::{RedisCmd, RedisKey, RedisValue, RespValue};
+use config::Config;
+use storage::Databases;
+use types::{RedisCmd, RespValue};
 
 #[macro_use]
 extern crate log;
 
+/// Commands processed for a connection before letting other connections run
+const COMMANDS_PER_ROUND: usize = 64;
+
+/// Longest wait of a blocking command, the timer of tokio panics past about two years
+const MAX_BLOCK_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Commands queued while a client is blocked, the socket isn't read past them until it's unblocked
+const MAX_QUEUED_COMMANDS: usize = 1024;
+
+/// Run the command sent by the client and get the reply
+fn process(resp: RespValue, databases: &Databases, client: &mut Client) -> RespValue {
+    match RedisCmd::try_from(resp) {
+        Ok(cmd) => execute(cmd, databases, client),
+        Err(err) => {
+            error!("Error getting command: {:?}", err);
+            RespValue::Error(err.into(), None)
+        }
+    }
+}
+
+fn execute(cmd: RedisCmd, databases: &Databases, client: &mut Client) -> RespValue {
+    match cmd.execute(databases, client) {
+        Ok(frame) => frame,
+        Err(err) => {
+            error!("Error executing frame: {:?}", err);
+            RespValue::Error("NOT_IMPLEMENTED".into(), None)
+        }
+    }
+}
+
+/// Deadline of a blocking command, longer timeouts are capped to what the timer supports
+fn block_deadline(timeout: Duration) -> Instant {
+    Instant::now() + timeout.min(MAX_BLOCK_TIMEOUT)
+}
+
+/// Wait for the keys of the blocking command of a client, retrying the command every time one
+/// of them is created, until it doesn't block or the timeout expires
+/// Commands sent meanwhile are queued up to `MAX_QUEUED_COMMANDS`, returns None if the client
+/// disconnected
+async fn wait_blocked<T>(
+    framed: &mut Framed<T, RespCodec>,
+    databases: &Databases,
+    client: &mut Client,
+    queued: &mut VecDeque<RespValue>,
+) -> Option<RespValue>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut blocked = client.blocked.take()?;
+    // Retries keep the deadline of the first attempt
+    let deadline = blocked.timeout.map(block_deadline);
+    loop {
+        let ready = client.ready.clone();
+        let timeout = async {
+            match deadline {
+                Some(deadline) => tokio::time::delay_until(deadline.into()).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = ready.notified() => {}
+            _ = timeout => {
+                client.unblock(databases, &blocked);
+                return Some(RespValue::NullArray);
+            }
+            resp = framed.try_next(), if queued.len() < MAX_QUEUED_COMMANDS => {
+                match resp {
+                    Ok(Some(resp)) => queued.push_back(resp),
+                    _ => {
+                        client.unblock(databases, &blocked);
+                        return None;
+                    }
+                }
+                continue;
+            }
+        }
+        client.unblock(databases, &blocked);
+        let frame = execute(blocked.command, databases, client);
+        blocked = match client.blocked.take() {
+            Some(blocked) => blocked,
+            None => return Some(frame),
+        };
+    }
+}
+
 async fn decode(
     io: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Sync + Unpin,
-    storage: Arc<Mutex<HashMap<RedisKey, RedisValue>>>,
+    databases: Arc<Databases>,
+    config: Arc<Config>,
 ) {
-    let decoder = RespCodec::new();
+    let decoder = RespCodec::new(&config);
+    let mut client = Client::new();
     let mut framed = Framed::new(io, decoder);
+    let mut processed = 0;
+    // Commands received while blocked
+    let mut queued = VecDeque::new();
     loop {
-        let result = framed.try_next().await;
+        // Commands already buffered are processed without waiting on the socket, yield from
+        // time to time so clients pipelining lots of commands don't starve the rest
+        if processed == COMMANDS_PER_ROUND {
+            processed = 0;
+            tokio::task::yield_now().await
+        }
+        processed += 1;
+
+        let result = match queued.pop_front() {
+            Some(resp) => Ok(Some(resp)),
+            None => framed.try_next().await,
+        };
         match result {
             Ok(resp) => {
                 debug!("Decoded: {:?}", &resp);
-                match resp {
+                let resp = match resp {
                     None => break,
-                    Some(resp) => match RedisCmd::try_from(resp) {
-                        Ok(cmd) => {
-                            let storage = storage.clone();
-                            match cmd.execute(storage) {
-                                Ok(frame) => framed.send(frame).await.unwrap(),
-                                Err(err) => {
-                                    let frame = RespValue::Error("NOT_IMPLEMENTED".into(), None);
-                                    framed.send(frame).await.unwrap();
-                                    error!("Error executing frame: {:?}", err)
-                                }
-                            };
+                    Some(resp) => resp,
+                };
+                #[cfg(feature = "failpoints")]
+                {
+                    use failpoints::Failpoint;
+                    match failpoints::for_command(&resp) {
+                        Some(Failpoint::Delay(delay)) => tokio::time::delay_for(delay).await,
+                        Some(Failpoint::Error(err)) => {
+                            framed.send(RespValue::Error(err, None)).await.unwrap();
+                            continue;
                         }
-                        Err(err) => {
-                            let frame = RespValue::Error(err.into(), None);
-                            framed.send(frame).await.unwrap();
-                            error!("Error getting command: {:?}", err);
+                        Some(Failpoint::Drop) => {
+                            process(resp, &databases, &mut client);
+                            if let Some(blocked) = client.blocked.take() {
+                                client.unblock(&databases, &blocked);
+                            }
+                            continue;
                         }
-                    },
-                };
+                        None => {}
+                    }
+                }
+                let mut frame = process(resp, &databases, &mut client);
+                if client.blocked.is_some() {
+                    frame = match wait_blocked(&mut framed, &databases, &mut client, &mut queued)
+                        .await
+                    {
+                        Some(frame) => frame,
+                        None => break,
+                    };
+                }
+                framed.send(frame).await.unwrap();
             }
             Err(err) => {
                 debug!("Error creating codec: {:?}", err);
+                let frame = RespValue::Error("ERR Protocol error".into(), None);
+                let _ = framed.send(frame).await;
                 break;
             }
         };
     }
 }
 
-#[tokio::main]
-async fn main() {
+/// Create the listener socket, using net2 because tokio does not allow to set the backlog
+fn bind(config: &Config) -> io::Result<TcpListener> {
+    let addr = config
+        .addr()
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid bind address"))?;
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    builder.reuse_address(true)?;
+    let listener = builder.bind(addr)?.listen(config.tcp_backlog)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// Apply the socket options from the config to an accepted connection
+fn configure_socket(sock: &TcpStream, config: &Config) -> io::Result<()> {
+    sock.set_nodelay(config.tcp_nodelay)?;
+    if config.socket_sndbuf > 0 {
+        sock.set_send_buffer_size(config.socket_sndbuf)?;
+    }
+    if config.socket_rcvbuf > 0 {
+        sock.set_recv_buffer_size(config.socket_rcvbuf)?;
+    }
+    Ok(())
+}
+
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Accepts connections, recovering from accept errors instead of spinning on them
+struct Acceptor {
+    listener: TcpListener,
+    /// File descriptor kept in reserve, when we run out of descriptors it's released to accept
+    /// and close the pending connection, instead of leaving the client hanging in the backlog
+    reserve: Option<File>,
+}
+
+impl Acceptor {
+    fn new(listener: TcpListener) -> Acceptor {
+        Acceptor {
+            listener,
+            reserve: File::open("/dev/null").ok(),
+        }
+    }
+
+    /// Wait for the next connection
+    async fn accept(&mut self) -> TcpStream {
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+        loop {
+            let err = match self.listener.accept().await {
+                Ok((sock, _)) => return sock,
+                Err(err) => err,
+            };
+            match err.raw_os_error() {
+                // The connection failed before we could accept it, nothing to do
+                Some(libc::ECONNABORTED)
+                | Some(libc::ECONNRESET)
+                | Some(libc::EPROTO)
+                | Some(libc::EPERM)
+                | Some(libc::EINTR) => {
+                    debug!("Connection failed while accepting: {:?}", err);
+                    continue;
+                }
+                Some(libc::EMFILE) | Some(libc::ENFILE) if self.reserve.is_some() => {
+                    if self.reject() {
+                        warn!("Out of file descriptors, rejected connection: {:?}", err);
+                        continue;
+                    }
+                    // accept fails before checking for pending connections, nothing to reject
+                    // yet, wait for other connections to release their descriptors
+                    warn!("Out of file descriptors, retrying in {:?}", backoff);
+                }
+                _ => error!("Error accepting, retrying in {:?}: {:?}", backoff, err),
+            }
+            tokio::time::delay_for(backoff).await;
+            backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+        }
+    }
+
+    /// Free the reserved descriptor to accept and immediately close a pending connection
+    /// Returns false if there was no connection waiting
+    fn reject(&mut self) -> bool {
+        self.reserve.take();
+        let rejected = match self.listener.accept().now_or_never() {
+            Some(Ok((sock, addr))) => {
+                debug!("Rejected connection: {:?}", addr);
+                drop(sock);
+                stats::REJECTED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        };
+        self.reserve = File::open("/dev/null").ok();
+        rejected
+    }
+}
+
+/// Handle a client connection, recording its traffic if `capture-dir` is set
+async fn handle(sock: TcpStream, databases: Arc<Databases>, config: Arc<Config>) {
+    let dir = match &config.capture_dir {
+        Some(dir) => dir,
+        None => return decode(sock, databases, config).await,
+    };
+    let peer = sock
+        .peer_addr()
+        .map_or_else(|_| "unknown".into(), |addr| addr.to_string());
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = Path::new(dir).join(format!("{}-{}.capture", peer, started));
+    match Capture::new(sock, &path) {
+        Ok(capture) => {
+            debug!("Capturing connection {} to {:?}", peer, path);
+            decode(capture, databases, config).await
+        }
+        Err(err) => error!("Error creating capture {:?}: {:?}", path, err),
+    }
+}
+
+/// Feed the inbound traffic of a capture to an empty server, printing the replies
+async fn replay(path: &str, config: Arc<Config>) -> io::Result<()> {
+    let mut replay = Replay::open(Path::new(path))?;
+    let databases = Arc::new(Databases::new(config.databases));
+    decode(&mut replay, databases, config).await;
+
+    println!("{}", String::from_utf8_lossy(&replay.output));
+    if replay.output != replay.expected {
+        println!("Replies differ from the capture, captured replies:");
+        println!("{}", String::from_utf8_lossy(&replay.expected));
+    }
+    Ok(())
+}
+
+/// Time between active expiration cycles
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+/// Max time of an active expiration cycle
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+/// Expired keys removed on every round of an active expiration cycle
+const ACTIVE_EXPIRE_KEYS: usize = 20;
+
+/// Remove expired keys in the background, so their memory is reclaimed even if they are never
+/// accessed again
+/// Keys are taken in the order they expire, every cycle keeps going while there are expired keys,
+/// taking the lock of a database for one round of keys at a time
+async fn active_expire(databases: Arc<Databases>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let start = Instant::now();
+        for storage in databases.iter() {
+            loop {
+                let removed = storage.lock().unwrap().expire_due(ACTIVE_EXPIRE_KEYS);
+                if removed > 0 {
+                    debug!("Active expiration handled {} expired keys", removed);
+                }
+                if removed < ACTIVE_EXPIRE_KEYS || start.elapsed() > ACTIVE_EXPIRE_BUDGET {
+                    break;
+                }
+            }
+            if start.elapsed() > ACTIVE_EXPIRE_BUDGET {
+                break;
+            }
+        }
+    }
+}
+
+/// Log a diagnostics report every time the process receives SIGUSR1
+#[cfg(unix)]
+async fn diagnostics(databases: Arc<Databases>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(err) => return error!("Error listening for SIGUSR1: {:?}", err),
+    };
+    while signals.recv().await.is_some() {
+        let keys: usize = databases
+            .iter()
+            .map(|storage| storage.lock().unwrap().len())
+            .sum();
+        warn!(
+            "Diagnostics: keys={} connected_clients={} rejected_connections={}",
+            keys,
+            stats::CONNECTED_CLIENTS.load(Ordering::Relaxed),
+            stats::REJECTED_CONNECTIONS.load(Ordering::Relaxed),
+        );
+        // Keyspace of every database with keys, like the keyspace section of redis INFO
+        for (index, storage) in databases.iter().enumerate() {
+            let storage = storage.lock().unwrap();
+            if storage.len() > 0 {
+                warn!(
+                    "Diagnostics: db{}:keys={},expires={}",
+                    index,
+                    storage.len(),
+                    storage.expires()
+                );
+            }
+        }
+    }
+}
+
+/// Pin the current thread to the given cpus
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) {
+    // Safe because cpu_set_t is a plain bitmask and it outlives the call
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        warn!(
+            "Error setting cpu affinity: {:?}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+fn main() {
     env_logger::init();
-    let addr = "127.0.0.1:6142";
-    let mut listener = TcpListener::bind(addr).await.unwrap();
-    let storage = Arc::new(Mutex::new(HashMap::new()));
+    let config = match Config::from_args() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut builder = tokio::runtime::Builder::new();
+    builder.threaded_scheduler().enable_all();
+    #[cfg(target_os = "linux")]
+    {
+        if !config.server_cpulist.is_empty() {
+            let cpus = config.server_cpulist.clone();
+            builder.on_thread_start(move || set_cpu_affinity(&cpus));
+        }
+    }
+    let mut runtime = match builder.build() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Error starting runtime: {}", err);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(run(config));
+}
+
+async fn run(config: Arc<Config>) {
+    if let Some(path) = &config.replay {
+        if let Err(err) = replay(path, config.clone()).await {
+            eprintln!("Error replaying {}: {}", path, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let listener = match bind(&config) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Error listening on {}: {}", config.addr(), err);
+            std::process::exit(1);
+        }
+    };
+    let databases = Arc::new(Databases::new(config.databases));
+    tokio::spawn(active_expire(databases.clone()));
+    #[cfg(unix)]
+    tokio::spawn(diagnostics(databases.clone()));
     let server = async move {
-        let mut incoming = listener.incoming();
-        while let Some(conn) = incoming.next().await {
-            match conn {
-                Err(err) => eprintln!("Error accepting: {:?}", err),
-                Ok(sock) => {
-                    debug!("Connection: {:?}", sock.peer_addr());
-                    let storage = storage.clone();
-                    tokio::spawn(async move {
-                        // let (reader, writer) = sock.split();
-                        decode(sock, storage).await;
-                    });
-                }
-            };
+        let mut acceptor = Acceptor::new(listener);
+        loop {
+            let sock = acceptor.accept().await;
+            debug!("Connection: {:?}", sock.peer_addr());
+            if let Err(err) = configure_socket(&sock, &config) {
+                warn!("Error configuring socket: {:?}", err);
+            }
+            let databases = databases.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                // let (reader, writer) = sock.split();
+                stats::CONNECTED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+                handle(sock, databases, config).await;
+                stats::CONNECTED_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+            });
         }
     };
 
@@ -0,0 +1,231 @@
+use bytes::{Buf, BytesMut};
+use std::collections::VecDeque;
+use std::str;
+
+use crate::types::{BulkString, RespValue};
+
+/// Hand-written incremental parser for the subset of resp2 sent by clients, see `resp_parser` in
+/// codec for the combine version
+/// The parser keeps the array being parsed between calls, so a frame arriving in many small
+/// chunks doesn't need to be parsed again from the start
+pub struct RespParser {
+    /// Elements missing and elements already parsed of the current array
+    array: Option<(usize, VecDeque<RespValue>)>,
+    /// Length of the bulk string we are waiting for, its header was already consumed
+    bulk: Option<usize>,
+    /// Longest bulk string accepted, see `Config::proto_max_bulk_len`
+    max_bulk_len: usize,
+}
+
+/// Find the next line, returns the position of `\r\n`
+fn find_line(src: &[u8]) -> Option<usize> {
+    src.windows(2).position(|window| window == b"\r\n")
+}
+
+/// Parse a resp integer, ie. the length in `*3` or `$5`
+fn parse_integer(line: &[u8]) -> Result<i64, String> {
+    str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.trim().parse().ok())
+        .ok_or_else(|| "Protocol error: invalid integer".to_string())
+}
+
+impl RespParser {
+    pub fn new(max_bulk_len: usize) -> RespParser {
+        RespParser {
+            array: None,
+            bulk: None,
+            max_bulk_len,
+        }
+    }
+
+    /// Parse the next command from `src`, consuming its bytes
+    /// Returns `None` when more input is needed
+    pub fn parse(
+        &mut self,
+        src: &mut BytesMut,
+        inline_commands: bool,
+    ) -> Result<Option<RespValue>, String> {
+        if self.array.is_none() {
+            let end = match find_line(src) {
+                Some(end) => end,
+                None => return Ok(None),
+            };
+            if src[0] != b'*' {
+                if !inline_commands {
+                    return Err(format!(
+                        "Protocol error: expected '*', got '{}'",
+                        src[0] as char
+                    ));
+                }
+                return self.parse_inline(src, end).map(Some);
+            }
+
+            let length = parse_integer(&src[1..end])?;
+            src.advance(end + 2);
+            if length < 0 {
+                return Ok(Some(RespValue::Null));
+            }
+            // Don't trust the client with the allocation size
+            let capacity = (length as usize).min(1024);
+            self.array = Some((length as usize, VecDeque::with_capacity(capacity)));
+        }
+
+        while let Some((missing, _)) = self.array {
+            if missing == 0 {
+                break;
+            }
+            let element = match self.parse_bulk(src)? {
+                Some(element) => element,
+                None => return Ok(None),
+            };
+            if let Some((missing, elements)) = &mut self.array {
+                *missing -= 1;
+                elements.push_back(element);
+            }
+        }
+
+        Ok(self
+            .array
+            .take()
+            .map(|(_, elements)| RespValue::Array(elements)))
+    }
+
+    /// Inline command, a line with the arguments splited by whitespace
+    fn parse_inline(&mut self, src: &mut BytesMut, end: usize) -> Result<RespValue, String> {
+        let line = src.split_to(end + 2);
+        let line = str::from_utf8(&line[..end]).map_err(|err| err.to_string())?;
+        Ok(RespValue::Array(
+            line.split_whitespace()
+                .map(|part| RespValue::BulkString(BulkString(part.into())))
+                .collect(),
+        ))
+    }
+
+    /// Binary friendly string, element of an array
+    fn parse_bulk(&mut self, src: &mut BytesMut) -> Result<Option<RespValue>, String> {
+        let length = match self.bulk {
+            Some(length) => length,
+            None => {
+                let end = match find_line(src) {
+                    Some(end) => end,
+                    None => return Ok(None),
+                };
+                if src[0] != b'$' {
+                    return Err(format!(
+                        "Protocol error: expected '$', got '{}'",
+                        src[0] as char
+                    ));
+                }
+                let length = parse_integer(&src[1..end])?;
+                src.advance(end + 2);
+                if length < 0 {
+                    return Ok(Some(RespValue::Null));
+                }
+                // Checked before waiting for the data, we reserve space for all of it
+                if length as usize > self.max_bulk_len {
+                    return Err("Protocol error: invalid bulk length".into());
+                }
+                self.bulk = Some(length as usize);
+                length as usize
+            }
+        };
+
+        if src.len() < length + 2 {
+            src.reserve(length + 2 - src.len());
+            return Ok(None);
+        }
+        if &src[length..length + 2] != b"\r\n" {
+            return Err("Protocol error: invalid bulk length".into());
+        }
+        let data = src.split_to(length).to_vec();
+        src.advance(2);
+        self.bulk = None;
+        Ok(Some(RespValue::BulkString(BulkString(data))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments of a parsed command
+    fn args(resp: RespValue) -> Vec<Vec<u8>> {
+        match resp {
+            RespValue::Array(elements) => elements
+                .into_iter()
+                .map(|element| match element {
+                    RespValue::BulkString(value) => value.0,
+                    other => panic!("expected a bulk string, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("expected an array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn partial_frames() {
+        let frame = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
+        let mut parser = RespParser::new(1024);
+        let mut src = BytesMut::new();
+        // Fed a byte at a time, the command is ready only with the last one
+        for (position, byte) in frame.iter().enumerate() {
+            src.extend_from_slice(&[*byte]);
+            let parsed = parser.parse(&mut src, false).unwrap();
+            if position + 1 < frame.len() {
+                assert!(parsed.is_none());
+            } else {
+                assert_eq!(
+                    args(parsed.unwrap()),
+                    vec![b"GET".to_vec(), b"hello".to_vec()]
+                );
+            }
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn pipelined_frames() {
+        let mut parser = RespParser::new(1024);
+        let mut src = BytesMut::from(&b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n*1\r\n$4"[..]);
+        for _ in 0..2 {
+            let parsed = parser.parse(&mut src, false).unwrap().unwrap();
+            assert_eq!(args(parsed), vec![b"PING".to_vec()]);
+        }
+        assert!(parser.parse(&mut src, false).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_bulk_length() {
+        let mut parser = RespParser::new(4);
+        let mut src = BytesMut::from(&b"*1\r\n$5\r\n"[..]);
+        assert!(parser.parse(&mut src, false).is_err());
+
+        let mut parser = RespParser::new(1024);
+        let mut src = BytesMut::from(&b"*1\r\n$9223372036854775807\r\n"[..]);
+        assert!(parser.parse(&mut src, false).is_err());
+    }
+
+    #[test]
+    fn bulk_length_not_matching_data() {
+        let mut parser = RespParser::new(1024);
+        let mut src = BytesMut::from(&b"*1\r\n$2\r\nabc\r\n"[..]);
+        assert!(parser.parse(&mut src, false).is_err());
+    }
+
+    #[test]
+    fn inline_commands() {
+        let mut parser = RespParser::new(1024);
+        let mut src = BytesMut::from(&b"SET  key value\r\nPING\r\n"[..]);
+        let parsed = parser.parse(&mut src, true).unwrap().unwrap();
+        assert_eq!(
+            args(parsed),
+            vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]
+        );
+        let parsed = parser.parse(&mut src, true).unwrap().unwrap();
+        assert_eq!(args(parsed), vec![b"PING".to_vec()]);
+
+        let mut src = BytesMut::from(&b"PING\r\n"[..]);
+        assert!(parser.parse(&mut src, false).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+use rand::seq::SliceRandom;
+
+/// Most elements replied for a negative count, they can repeat so unlike positive counts the
+/// reply is not limited by the size of the collection, and it's built holding the lock of the
+/// database
+pub const MAX_REPEATED: usize = 1024 * 1024;
+
+/// Random elements of HRANDFIELD and ZRANDMEMBER, `count` distinct ones when it's positive, up
+/// to all of them, or `-count` maybe repeated when it's negative, up to `MAX_REPEATED`
+pub fn sample<T: Copy>(elements: impl Iterator<Item = T>, count: i64) -> Vec<T> {
+    let mut rng = rand::thread_rng();
+    let mut all: Vec<_> = elements.collect();
+    if count >= 0 {
+        let amount = all.len().min(count as usize);
+        return all.partial_shuffle(&mut rng, amount).0.to_vec();
+    }
+    let amount = MAX_REPEATED.min(count.unsigned_abs() as usize);
+    let mut sample = Vec::with_capacity(amount);
+    for _ in 0..amount {
+        sample.extend(all.choose(&mut rng));
+    }
+    sample
+}
@@ -0,0 +1,559 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+
+use crate::types::{BulkString, MAX_STRING_LEN};
+use crate::value::{Score, SortedSet, Value};
+
+/// Rdb version written in the payloads, loaded by redis 5 and newer
+const RDB_VERSION: u16 = 9;
+/// Newest rdb version we can load, redis 7.2
+const RDB_MAX_VERSION: u16 = 11;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+/// Node of a quicklist holding a single big element instead of a listpack
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+const BAD_FORMAT: &str = "ERR Bad data format";
+
+/// Most bytes lzf can decompress from a byte, a back reference of 3 bytes writes up to 264 bytes
+const LZF_MAX_EXPANSION: usize = 88;
+
+/// Serialize a value like redis DUMP, the value in rdb format followed by the rdb version and a
+/// crc64 of everything before it
+/// Values are written with the plain encodings, redis converts them to its compact encodings
+/// when loading them
+pub fn dump(value: &Value) -> Result<Vec<u8>, &'static str> {
+    let mut payload = vec![];
+    match value {
+        Value::String(value) => {
+            payload.push(TYPE_STRING);
+            write_string(&mut payload, &value.0);
+        }
+        Value::List(list) => {
+            payload.push(TYPE_LIST);
+            write_length(&mut payload, list.len() as u64);
+            for element in list {
+                write_string(&mut payload, &element.0);
+            }
+        }
+        Value::Set(set) => {
+            payload.push(TYPE_SET);
+            write_length(&mut payload, set.len() as u64);
+            for member in set {
+                write_string(&mut payload, &member.0);
+            }
+        }
+        Value::ZSet(zset) => {
+            payload.push(TYPE_ZSET_2);
+            write_length(&mut payload, zset.len() as u64);
+            // From the highest score like redis
+            for (Score(score), member) in zset.ordered().iter().rev() {
+                write_string(&mut payload, &member.0);
+                payload.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Hash(hash) => {
+            payload.push(TYPE_HASH);
+            write_length(&mut payload, hash.len() as u64);
+            // Expirations of fields are not serialized
+            for (field, value) in hash.iter() {
+                write_string(&mut payload, &field.0);
+                write_string(&mut payload, &value.0);
+            }
+        }
+        Value::Stream(_) => return Err("ERR DUMP of streams is not supported"),
+    }
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    Ok(payload)
+}
+
+/// Load a payload created by `dump` or by redis DUMP, checking its version and checksum
+pub fn restore(payload: &[u8]) -> Result<Value, &'static str> {
+    const CHECK_ERROR: &str = "ERR DUMP payload version or checksum are wrong";
+    if payload.len() < 10 {
+        return Err(CHECK_ERROR);
+    }
+    let (data, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().unwrap());
+    if version > RDB_MAX_VERSION || crc64(0, &payload[..payload.len() - 8]) != crc {
+        return Err(CHECK_ERROR);
+    }
+
+    let mut reader = Reader { data, position: 0 };
+    let value = reader.read_value()?;
+    if reader.position != data.len() {
+        return Err(BAD_FORMAT);
+    }
+    Ok(value)
+}
+
+/// Length encoding, the first two bits tell how many bytes are used
+fn write_length(buf: &mut Vec<u8>, length: u64) {
+    if length < 1 << 6 {
+        buf.push(length as u8);
+    } else if length < 1 << 14 {
+        buf.push(0x40 | (length >> 8) as u8);
+        buf.push(length as u8);
+    } else if length <= u64::from(u32::MAX) {
+        buf.push(0x80);
+        buf.extend_from_slice(&(length as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&length.to_be_bytes());
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &[u8]) {
+    write_length(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Reads a value in rdb format
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// Length or special encoding of a string, see `Reader::read_length_or_encoding`
+enum Length {
+    Length(u64),
+    Encoded(u8),
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.position.checked_add(len).ok_or(BAD_FORMAT)?;
+        let bytes = self.data.get(self.position..end).ok_or(BAD_FORMAT)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length, &'static str> {
+        let first = self.read_u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Length(u64::from(first & 0x3f)),
+            1 => Length::Length(u64::from(first & 0x3f) << 8 | u64::from(self.read_u8()?)),
+            3 => Length::Encoded(first & 0x3f),
+            _ => match first {
+                0x80 => Length::Length(u64::from(u32::from_be_bytes(
+                    self.read_bytes(4)?.try_into().unwrap(),
+                ))),
+                0x81 => Length::Length(u64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap())),
+                _ => return Err(BAD_FORMAT),
+            },
+        })
+    }
+
+    fn read_length(&mut self) -> Result<u64, &'static str> {
+        match self.read_length_or_encoding()? {
+            Length::Length(length) => Ok(length),
+            Length::Encoded(_) => Err(BAD_FORMAT),
+        }
+    }
+
+    /// Number of elements of a collection, every element takes at least a byte of what is left
+    fn read_count(&mut self) -> Result<u64, &'static str> {
+        let count = self.read_length()?;
+        if count > (self.data.len() - self.position) as u64 {
+            return Err(BAD_FORMAT);
+        }
+        Ok(count)
+    }
+
+    /// Length of a string, up to the longest string
+    fn read_string_length(&mut self) -> Result<usize, &'static str> {
+        match self.read_length()? {
+            length if length <= MAX_STRING_LEN as u64 => Ok(length as usize),
+            _ => Err(BAD_FORMAT),
+        }
+    }
+
+    /// Strings can be stored as is, as an integer or compressed with lzf
+    fn read_string(&mut self) -> Result<Vec<u8>, &'static str> {
+        match self.read_length_or_encoding()? {
+            Length::Length(length) if length <= MAX_STRING_LEN as u64 => {
+                Ok(self.read_bytes(length as usize)?.to_vec())
+            }
+            Length::Length(_) => Err(BAD_FORMAT),
+            Length::Encoded(0) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(1) => {
+                let value = i16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(2) => {
+                let value = i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(3) => {
+                let compressed_len = self.read_string_length()?;
+                let len = self.read_string_length()?;
+                lzf_decompress(self.read_bytes(compressed_len)?, len)
+            }
+            Length::Encoded(_) => Err(BAD_FORMAT),
+        }
+    }
+
+    /// Score of the old zset encoding, a string with special values for nan and infinities
+    fn read_score(&mut self) -> Result<f64, &'static str> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_score(self.read_bytes(len as usize)?),
+        }
+    }
+
+    fn read_value(&mut self) -> Result<Value, &'static str> {
+        let value = match self.read_u8()? {
+            TYPE_STRING => Value::String(BulkString(self.read_string()?)),
+            TYPE_LIST => {
+                let len = self.read_count()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.push_back(BulkString(self.read_string()?));
+                }
+                Value::List(list)
+            }
+            TYPE_SET => {
+                let len = self.read_count()?;
+                let mut set = HashSet::new();
+                for _ in 0..len {
+                    set.insert(BulkString(self.read_string()?));
+                }
+                Value::Set(set)
+            }
+            rdb_type @ TYPE_ZSET | rdb_type @ TYPE_ZSET_2 => {
+                let len = self.read_count()?;
+                let mut zset = SortedSet::default();
+                for _ in 0..len {
+                    let member = BulkString(self.read_string()?);
+                    let score = if rdb_type == TYPE_ZSET {
+                        self.read_score()?
+                    } else {
+                        f64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap())
+                    };
+                    if score.is_nan() {
+                        return Err(BAD_FORMAT);
+                    }
+                    zset.insert(member, score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_HASH => {
+                let len = self.read_count()?;
+                let mut hash = HashMap::new();
+                for _ in 0..len {
+                    let field = BulkString(self.read_string()?);
+                    hash.insert(field, BulkString(self.read_string()?));
+                }
+                Value::Hash(hash.into())
+            }
+            TYPE_SET_INTSET => Value::Set(read_intset(&self.read_string()?)?.collect()),
+            TYPE_SET_LISTPACK => Value::Set(read_listpack(&self.read_string()?)?.collect()),
+            TYPE_HASH_LISTPACK => {
+                let mut entries = read_listpack(&self.read_string()?)?;
+                let mut hash = HashMap::new();
+                while let Some(field) = entries.next() {
+                    hash.insert(field, entries.next().ok_or(BAD_FORMAT)?);
+                }
+                Value::Hash(hash.into())
+            }
+            TYPE_ZSET_LISTPACK => {
+                let mut entries = read_listpack(&self.read_string()?)?;
+                let mut zset = SortedSet::default();
+                while let Some(member) = entries.next() {
+                    let score = parse_score(&entries.next().ok_or(BAD_FORMAT)?.0)?;
+                    zset.insert(member, score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_count()?;
+                let mut list = VecDeque::new();
+                for _ in 0..nodes {
+                    let container = self.read_length()?;
+                    let node = self.read_string()?;
+                    if container == QUICKLIST_NODE_PLAIN {
+                        list.push_back(BulkString(node));
+                    } else {
+                        list.extend(read_listpack(&node)?);
+                    }
+                }
+                Value::List(list)
+            }
+            _ => return Err(BAD_FORMAT),
+        };
+        Ok(value)
+    }
+}
+
+fn parse_score(score: &[u8]) -> Result<f64, &'static str> {
+    let score = std::str::from_utf8(score).map_err(|_| BAD_FORMAT)?;
+    match score {
+        "inf" | "+inf" => Ok(f64::INFINITY),
+        "-inf" => Ok(f64::NEG_INFINITY),
+        score => match score.parse::<f64>() {
+            Ok(score) if !score.is_nan() => Ok(score),
+            _ => Err(BAD_FORMAT),
+        },
+    }
+}
+
+/// Set of integers, the size of the integers followed by the number of integers, little endian
+fn read_intset(data: &[u8]) -> Result<impl Iterator<Item = BulkString> + '_, &'static str> {
+    if data.len() < 8 {
+        return Err(BAD_FORMAT);
+    }
+    let size = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    if ![2, 4, 8].contains(&size) || data.len() != 8 + size * len {
+        return Err(BAD_FORMAT);
+    }
+    Ok(data[8..].chunks(size).map(|chunk| {
+        let value = match chunk.len() {
+            2 => i64::from(i16::from_le_bytes(chunk.try_into().unwrap())),
+            4 => i64::from(i32::from_le_bytes(chunk.try_into().unwrap())),
+            _ => i64::from_le_bytes(chunk.try_into().unwrap()),
+        };
+        BulkString(value.to_string().into_bytes())
+    }))
+}
+
+/// Elements of a listpack, integers are converted to strings
+/// Header of total bytes and number of elements, then every element encoded with its type and
+/// followed by its length backwards, and a 0xff terminator
+fn read_listpack(data: &[u8]) -> Result<impl Iterator<Item = BulkString>, &'static str> {
+    let mut elements = vec![];
+    let mut position = 6;
+    loop {
+        let first = *data.get(position).ok_or(BAD_FORMAT)?;
+        if first == 0xff {
+            break;
+        }
+        let bytes = |start: usize, len: usize| data.get(start..start + len).ok_or(BAD_FORMAT);
+        // Length of the header and of the data
+        let (header, len, element) = if first & 0x80 == 0 {
+            (1, 0, BulkString(u64::from(first).to_string().into_bytes()))
+        } else if first & 0xc0 == 0x80 {
+            let len = usize::from(first & 0x3f);
+            (1, len, BulkString(bytes(position + 1, len)?.to_vec()))
+        } else if first & 0xe0 == 0xc0 {
+            let raw =
+                u16::from(first & 0x1f) << 8 | u16::from(*bytes(position + 1, 1)?.first().unwrap());
+            // 13 bits two's complement
+            let value = ((raw << 3) as i16) >> 3;
+            (2, 0, BulkString(value.to_string().into_bytes()))
+        } else if first & 0xf0 == 0xe0 {
+            let len = usize::from(first & 0x0f) << 8 | usize::from(bytes(position + 1, 1)?[0]);
+            (2, len, BulkString(bytes(position + 2, len)?.to_vec()))
+        } else {
+            let int = |len: usize| -> Result<i64, &'static str> {
+                let mut buf = [0; 8];
+                buf[..len].copy_from_slice(bytes(position + 1, len)?);
+                // Sign extension of the bytes read
+                let shift = 64 - 8 * len as u32;
+                Ok((i64::from_le_bytes(buf) << shift) >> shift)
+            };
+            match first {
+                0xf0 => {
+                    let len = u32::from_le_bytes(bytes(position + 1, 4)?.try_into().unwrap());
+                    let len = len as usize;
+                    (5, len, BulkString(bytes(position + 5, len)?.to_vec()))
+                }
+                0xf1 => (3, 0, BulkString(int(2)?.to_string().into_bytes())),
+                0xf2 => (4, 0, BulkString(int(3)?.to_string().into_bytes())),
+                0xf3 => (5, 0, BulkString(int(4)?.to_string().into_bytes())),
+                0xf4 => (9, 0, BulkString(int(8)?.to_string().into_bytes())),
+                _ => return Err(BAD_FORMAT),
+            }
+        };
+        let entry_len = header + len;
+        position += entry_len + backlen_size(entry_len);
+        elements.push(element);
+    }
+    Ok(elements.into_iter())
+}
+
+/// Bytes of the length of a listpack entry written after it, like `lpEncodeBacklen` of redis
+fn backlen_size(entry_len: usize) -> usize {
+    match entry_len {
+        0..=127 => 1,
+        128..16383 => 2,
+        16383..2_097_151 => 3,
+        2_097_151..268_435_455 => 4,
+        _ => 5,
+    }
+}
+
+/// Decompress lzf data, a sequence of literal runs and back references
+/// `len` is checked against what the input can expand to, the output grows as it's written
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    if len > MAX_STRING_LEN || len > input.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return Err(BAD_FORMAT);
+    }
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < input.len() {
+        let control = usize::from(input[position]);
+        position += 1;
+        if control < 32 {
+            // Literal run of control + 1 bytes
+            let run = input
+                .get(position..position + control + 1)
+                .ok_or(BAD_FORMAT)?;
+            if output.len() + run.len() > len {
+                return Err(BAD_FORMAT);
+            }
+            output.extend_from_slice(run);
+            position += control + 1;
+        } else {
+            // Back reference, the length is stored in the 3 high bits and maybe in the next byte
+            let mut run = control >> 5;
+            if run == 7 {
+                run += usize::from(*input.get(position).ok_or(BAD_FORMAT)?);
+                position += 1;
+            }
+            let offset =
+                ((control & 0x1f) << 8) + usize::from(*input.get(position).ok_or(BAD_FORMAT)?) + 1;
+            position += 1;
+            let start = output.len().checked_sub(offset).ok_or(BAD_FORMAT)?;
+            if output.len() + run + 2 > len {
+                return Err(BAD_FORMAT);
+            }
+            // Byte by byte, the reference can overlap the bytes being written
+            for index in start..start + run + 2 {
+                output.push(output[index]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(BAD_FORMAT);
+    }
+    Ok(output)
+}
+
+/// crc64 with the Jones polynomial, used by redis for rdb payloads
+fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    for &byte in data {
+        crc ^= u64::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(value: &str) -> BulkString {
+        BulkString(value.as_bytes().to_vec())
+    }
+
+    fn round_trip(value: &Value) -> Value {
+        restore(&dump(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn dump_restore_round_trips() {
+        let string = Value::String(bulk("hello"));
+        assert_eq!(round_trip(&string).as_string().unwrap(), &bulk("hello"));
+
+        // Long enough for a length of 2 bytes
+        let list: VecDeque<_> = (0..100).map(|index| bulk(&index.to_string())).collect();
+        let restored = round_trip(&Value::List(list.clone()));
+        assert_eq!(restored.as_list().unwrap(), &list);
+
+        let set: HashSet<_> = vec![bulk("a"), bulk("b"), bulk("")].into_iter().collect();
+        let restored = round_trip(&Value::Set(set.clone()));
+        assert_eq!(restored.as_set().unwrap(), &set);
+
+        let mut zset = SortedSet::default();
+        zset.insert(bulk("low"), -1.5);
+        zset.insert(bulk("high"), f64::INFINITY);
+        let restored = round_trip(&Value::ZSet(zset.clone()));
+        assert_eq!(restored.as_zset().unwrap().ordered(), zset.ordered());
+
+        let fields: HashMap<_, _> = vec![(bulk("f1"), bulk("v1")), (bulk("f2"), bulk("v2"))]
+            .into_iter()
+            .collect();
+        let restored = round_trip(&Value::Hash(fields.clone().into()));
+        assert_eq!(**restored.as_hash().unwrap(), fields);
+    }
+
+    #[test]
+    fn restore_redis_payload() {
+        // DUMP of the integer 10, from the documentation of redis
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        let value = restore(payload).unwrap();
+        assert_eq!(value.as_string().unwrap(), &bulk("10"));
+    }
+
+    #[test]
+    fn restore_rejects_bad_payloads() {
+        let mut payload = dump(&Value::String(bulk("hello"))).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(restore(&payload).is_err());
+
+        // An lzf string claiming a huge length
+        let mut payload = vec![TYPE_STRING, 0xc3, 0x05, 0x81];
+        payload.extend_from_slice(&(1u64 << 62).to_be_bytes());
+        payload.extend_from_slice(b"\x00a\xe0\x14\x00");
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let crc = crc64(0, &payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(restore(&payload).unwrap_err(), BAD_FORMAT);
+    }
+
+    #[test]
+    fn backlen_size_boundaries() {
+        assert_eq!(backlen_size(127), 1);
+        assert_eq!(backlen_size(128), 2);
+        assert_eq!(backlen_size(16382), 2);
+        assert_eq!(backlen_size(16383), 3);
+        assert_eq!(backlen_size(2_097_150), 3);
+        assert_eq!(backlen_size(2_097_151), 4);
+        assert_eq!(backlen_size(268_435_454), 4);
+        assert_eq!(backlen_size(268_435_455), 5);
+    }
+
+    #[test]
+    fn listpack_entry_after_backlen_boundary() {
+        // A 32 bits string header and 16378 bytes, 16383 bytes with a backlen of 3 bytes
+        let mut listpack = vec![0; 6];
+        listpack.push(0xf0);
+        listpack.extend_from_slice(&16378u32.to_le_bytes());
+        listpack.resize(listpack.len() + 16378, b'a');
+        listpack.extend_from_slice(&[0, 0, 0]);
+        listpack.extend_from_slice(&[0x05, 1, 0xff]);
+        let elements: Vec<_> = read_listpack(&listpack).unwrap().collect();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].0.len(), 16378);
+        assert_eq!(elements[1], BulkString(b"5".to_vec()));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+
+use crate::storage::Storage;
+use crate::types::{BulkString, RedisKey, RespValue};
+use crate::value::Value;
+
+/// Add members to a set, creating it if needed, replies the number of new members
+pub fn sadd(
+    storage: &mut Storage,
+    key: &RedisKey,
+    members: Vec<BulkString>,
+) -> Result<RespValue, &'static str> {
+    let set = storage
+        .get_or_insert_with(key, || Value::Set(HashSet::new()))
+        .as_set_mut()?;
+    let mut added = 0;
+    for member in members {
+        if set.insert(member) {
+            added += 1;
+        }
+    }
+    Ok(RespValue::Integer(added))
+}
+
+/// Remove members of a set, the key is removed with the last member
+pub fn srem(
+    storage: &mut Storage,
+    key: &RedisKey,
+    members: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let set = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_set_mut()?,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let removed = members.iter().filter(|member| set.remove(member)).count();
+    storage.remove_if_empty(key);
+    Ok(RespValue::Integer(removed as i64))
+}
+
+/// Move a member from one set to another, replies 1 if it was moved
+pub fn smove(
+    storage: &mut Storage,
+    source: &RedisKey,
+    destination: &RedisKey,
+    member: &BulkString,
+) -> Result<RespValue, &'static str> {
+    // Check the type of both keys before changing anything
+    let exists = match storage.get(source) {
+        Some(entry) => entry.value.as_set()?.contains(member),
+        None => false,
+    };
+    if let Some(entry) = storage.get(destination) {
+        entry.value.as_set()?;
+    }
+    if !exists || source == destination {
+        return Ok(RespValue::Integer(exists.into()));
+    }
+    if let Some(entry) = storage.get_mut(source) {
+        entry.value.as_set_mut()?.remove(member);
+    }
+    storage.remove_if_empty(source);
+    storage
+        .get_or_insert_with(destination, || Value::Set(HashSet::new()))
+        .as_set_mut()?
+        .insert(member.clone());
+    Ok(RespValue::Integer(1))
+}
+
+/// All the members of a set
+pub fn smembers(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let set = match storage.get(key) {
+        Some(entry) => entry.value.as_set()?,
+        None => return Ok(RespValue::Array(Default::default())),
+    };
+    Ok(RespValue::Array(
+        set.iter()
+            .map(|member| RespValue::BulkString(member.clone()))
+            .collect(),
+    ))
+}
+
+pub fn sismember(
+    storage: &mut Storage,
+    key: &RedisKey,
+    member: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let exists = match storage.get(key) {
+        Some(entry) => entry.value.as_set()?.contains(member),
+        None => false,
+    };
+    Ok(RespValue::Integer(exists.into()))
+}
+
+/// Check several members at once, replies 1 or 0 for each of them
+pub fn smismember(
+    storage: &mut Storage,
+    key: &RedisKey,
+    members: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let set = match storage.get(key) {
+        Some(entry) => Some(entry.value.as_set()?),
+        None => None,
+    };
+    Ok(RespValue::Array(
+        members
+            .iter()
+            .map(|member| {
+                let exists = set.is_some_and(|set| set.contains(member));
+                RespValue::Integer(exists.into())
+            })
+            .collect(),
+    ))
+}
+
+/// Number of members of a set
+pub fn scard(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry.value.as_set()?.len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Operations combining several sets
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Inter,
+    Union,
+    /// Members of the first set not in the others
+    Diff,
+}
+
+/// Sets of several keys, missing keys are `empty`
+fn sets<'a>(
+    storage: &'a mut Storage,
+    keys: &[RedisKey],
+    empty: &'a HashSet<BulkString>,
+) -> Result<Vec<&'a HashSet<BulkString>>, &'static str> {
+    storage
+        .get_many(keys)
+        .into_iter()
+        .map(|entry| match entry {
+            Some(entry) => entry.value.as_set(),
+            None => Ok(empty),
+        })
+        .collect()
+}
+
+/// Members resulting of an operation on several sets, missing keys are empty sets
+fn members_of(
+    storage: &mut Storage,
+    operation: Operation,
+    keys: &[RedisKey],
+) -> Result<HashSet<BulkString>, &'static str> {
+    let empty = HashSet::new();
+    let sets = sets(storage, keys, &empty)?;
+    let (first, rest) = match sets.split_first() {
+        Some((first, rest)) => (first, rest),
+        None => return Ok(HashSet::new()),
+    };
+    let members = match operation {
+        Operation::Union => sets.iter().flat_map(|set| set.iter()).cloned().collect(),
+        Operation::Inter => {
+            // Check the members of the smallest set against the others
+            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap_or(first);
+            smallest
+                .iter()
+                .filter(|member| sets.iter().all(|set| set.contains(member)))
+                .cloned()
+                .collect()
+        }
+        Operation::Diff => first
+            .iter()
+            .filter(|member| !rest.iter().any(|set| set.contains(member)))
+            .cloned()
+            .collect(),
+    };
+    Ok(members)
+}
+
+/// Reply the members resulting of an operation on several sets
+pub fn combine(
+    storage: &mut Storage,
+    operation: Operation,
+    keys: &[RedisKey],
+) -> Result<RespValue, &'static str> {
+    let members = members_of(storage, operation, keys)?;
+    Ok(RespValue::Array(
+        members.into_iter().map(RespValue::BulkString).collect(),
+    ))
+}
+
+/// Store the members resulting of an operation on several sets, replacing the destination
+/// Replies the number of members stored, the destination is removed if there are none
+pub fn combine_store(
+    storage: &mut Storage,
+    operation: Operation,
+    destination: &RedisKey,
+    keys: &[RedisKey],
+) -> Result<RespValue, &'static str> {
+    let members = members_of(storage, operation, keys)?;
+    let len = members.len();
+    if members.is_empty() {
+        storage.remove(destination);
+    } else {
+        storage.set(destination.clone(), Value::Set(members));
+    }
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Number of members of the intersection of several sets, without building it
+/// Counting stops at `limit` unless it's 0
+pub fn sintercard(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    limit: usize,
+) -> Result<RespValue, &'static str> {
+    let empty = HashSet::new();
+    let sets = sets(storage, keys, &empty)?;
+    let smallest = match sets.iter().min_by_key(|set| set.len()) {
+        Some(smallest) => smallest,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let limit = match limit {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let count = smallest
+        .iter()
+        .filter(|member| sets.iter().all(|set| set.contains(member)))
+        .take(limit)
+        .count();
+    Ok(RespValue::Integer(count as i64))
+}
@@ -0,0 +1,7 @@
+use std::sync::atomic::AtomicU64;
+
+/// Connections closed right after accepting them because the server ran out of file descriptors
+pub static REJECTED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Clients currently connected
+pub static CONNECTED_CLIENTS: AtomicU64 = AtomicU64::new(0);
@@ -0,0 +1,475 @@
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Notify;
+
+use crate::types::RedisKey;
+use crate::value::Value;
+
+/// Current unix time in milliseconds
+/// Every time read for expirations goes through here
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as i64)
+}
+
+/// Value stored for a key
+#[derive(Debug)]
+pub struct Entry {
+    pub value: Value,
+    /// Unix time in milliseconds when the key expires, change it with `Storage::set_expiration`
+    /// so the active expiration finds the key
+    pub expires_at: Option<i64>,
+    /// Unix time in milliseconds of the last access by a command, for OBJECT IDLETIME and LRU
+    pub accessed_at: i64,
+}
+
+impl Entry {
+    pub fn new(value: Value) -> Entry {
+        Entry {
+            value,
+            expires_at: None,
+            accessed_at: now_ms(),
+        }
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/// Set of keys stored in a vec to pick random keys in O(1), for RANDOMKEY
+#[derive(Default)]
+struct KeySet {
+    keys: Vec<RedisKey>,
+    /// Position of every key in `keys`
+    positions: HashMap<RedisKey, usize>,
+}
+
+impl KeySet {
+    fn insert(&mut self, key: &RedisKey) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.clone(), self.keys.len());
+            self.keys.push(key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &RedisKey) {
+        if let Some(position) = self.positions.remove(key) {
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    fn random(&self) -> Option<&RedisKey> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        Some(&self.keys[rand::thread_rng().gen_range(0, self.keys.len())])
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.positions.clear();
+    }
+}
+
+/// Keys ordered by the time they expire, so the expired ones are found without checking the
+/// rest
+#[derive(Default)]
+struct Deadlines {
+    by_time: BTreeSet<(i64, RedisKey)>,
+    /// Deadline of every key in `by_time`
+    by_key: HashMap<RedisKey, i64>,
+}
+
+impl Deadlines {
+    /// Add a key or change its deadline
+    fn insert(&mut self, key: &RedisKey, deadline: i64) {
+        self.remove(key);
+        self.by_key.insert(key.clone(), deadline);
+        self.by_time.insert((deadline, key.clone()));
+    }
+
+    fn remove(&mut self, key: &RedisKey) {
+        if let Some(deadline) = self.by_key.remove(key) {
+            self.by_time.remove(&(deadline, key.clone()));
+        }
+    }
+
+    fn get(&self, key: &RedisKey) -> Option<i64> {
+        self.by_key.get(key).copied()
+    }
+
+    /// The key with the earliest deadline if it's at or before `now`
+    fn first_due(&self, now: i64) -> Option<&RedisKey> {
+        match self.by_time.iter().next() {
+            Some((deadline, key)) if *deadline <= now => Some(key),
+            _ => None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_time.clear();
+        self.by_key.clear();
+    }
+}
+
+/// Keyspace of the server, keys with an expiration in the past are handled as missing keys
+#[derive(Default)]
+pub struct Storage {
+    entries: HashMap<RedisKey, Entry>,
+    /// Every key of `entries`
+    keys: KeySet,
+    /// Keys with an expiration, by the time they expire
+    expiring: Deadlines,
+    /// Keys of hashes with fields having an expiration, by the time the first field expires
+    /// It can have keys that were removed or overwritten since then, or deadlines of fields that
+    /// were changed, they are fixed when found
+    expiring_fields: Deadlines,
+    /// Clients blocked on missing keys, by id, they are notified when the key is created
+    waiters: HashMap<RedisKey, Vec<(u64, Arc<Notify>)>>,
+}
+
+impl Storage {
+    pub fn new() -> Storage {
+        Default::default()
+    }
+
+    /// Insert an entry, adding its key to the index of keys
+    fn insert_entry(&mut self, key: RedisKey, entry: Entry) {
+        self.keys.insert(&key);
+        self.entries.insert(key, entry);
+    }
+
+    /// Remove an entry, and its key from the indexes of keys
+    fn remove_entry(&mut self, key: &RedisKey) -> Option<Entry> {
+        self.keys.remove(key);
+        self.expiring.remove(key);
+        self.entries.remove(key)
+    }
+
+    /// Lazy expiration, remove the key if it's expired, and the expired fields of a hash
+    /// Every access to a key goes through here, so expired keys are never seen by commands
+    /// Returns true if the key was removed
+    fn expire_if_needed(&mut self, key: &RedisKey) -> bool {
+        let now = now_ms();
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.is_expired(now),
+            None => false,
+        };
+        if expired {
+            self.remove_entry(key);
+            return true;
+        }
+        let fields_expired = self
+            .expiring_fields
+            .get(key)
+            .is_some_and(|next| next <= now);
+        fields_expired && self.expire_fields(key, now).1
+    }
+
+    /// Remove the expired fields of a hash, and the key if no field is left
+    /// Returns the number of fields removed and if the key was removed
+    fn expire_fields(&mut self, key: &RedisKey, now: i64) -> (usize, bool) {
+        let hash = match self.entries.get_mut(key).map(|entry| &mut entry.value) {
+            Some(Value::Hash(hash)) => hash,
+            _ => {
+                self.expiring_fields.remove(key);
+                return (0, false);
+            }
+        };
+        let removed = hash.remove_expired(now);
+        match hash.next_expiration() {
+            Some(expires_at) => self.expiring_fields.insert(key, expires_at),
+            None => self.expiring_fields.remove(key),
+        }
+        if hash.is_empty() {
+            self.remove_entry(key);
+            return (removed, true);
+        }
+        (removed, false)
+    }
+
+    /// Track the expiration of the fields of a hash, call it after changing them
+    pub fn track_field_expirations(&mut self, key: &RedisKey) {
+        let next = match self.entries.get(key).map(|entry| &entry.value) {
+            Some(Value::Hash(hash)) => hash.next_expiration(),
+            _ => None,
+        };
+        match next {
+            Some(expires_at) => self.expiring_fields.insert(key, expires_at),
+            None => self.expiring_fields.remove(key),
+        }
+    }
+
+    /// Get the entry of a key, updating its last access time
+    pub fn get(&mut self, key: &RedisKey) -> Option<&Entry> {
+        self.get_mut(key).map(|entry| &*entry)
+    }
+
+    /// Get the entry of a key to modify it, updating its last access time
+    pub fn get_mut(&mut self, key: &RedisKey) -> Option<&mut Entry> {
+        self.expire_if_needed(key);
+        let entry = self.entries.get_mut(key)?;
+        entry.accessed_at = now_ms();
+        Some(entry)
+    }
+
+    /// Get the entries of several keys at once, updating their last access time
+    pub fn get_many(&mut self, keys: &[RedisKey]) -> Vec<Option<&Entry>> {
+        for key in keys {
+            self.get_mut(key);
+        }
+        let entries = &self.entries;
+        keys.iter().map(|key| entries.get(key)).collect()
+    }
+
+    /// Get the entry of a key without updating its last access time, for commands inspecting
+    /// the key instead of its value (ie. EXISTS or TTL)
+    pub fn peek(&mut self, key: &RedisKey) -> Option<&Entry> {
+        self.expire_if_needed(key);
+        self.entries.get(key)
+    }
+
+    /// Get the value of a key to modify it, inserting `value()` when the key doesn't exist
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &RedisKey,
+        value: impl FnOnce() -> Value,
+    ) -> &mut Value {
+        if self.get_mut(key).is_none() {
+            self.set(key.clone(), value());
+        }
+        &mut self.entries.get_mut(key).unwrap().value
+    }
+
+    /// Remove a key holding an empty collection, called after removing elements
+    pub fn remove_if_empty(&mut self, key: &RedisKey) {
+        if matches!(self.entries.get(key), Some(entry) if entry.value.is_empty()) {
+            self.remove_entry(key);
+        }
+    }
+
+    /// Set the value of a key, discarding its expiration and its previous type
+    pub fn set(&mut self, key: RedisKey, value: Value) {
+        self.expiring.remove(&key);
+        self.wake_waiters(&key);
+        self.insert_entry(key, Entry::new(value));
+    }
+
+    /// Set the value of a key, keeping its expiration if it already exists
+    pub fn replace(&mut self, key: &RedisKey, value: Value) {
+        match self.get_mut(key) {
+            Some(entry) => entry.value = value,
+            None => self.set(key.clone(), value),
+        }
+    }
+
+    /// Insert an entry taken from another database, keeping its expiration
+    pub fn insert(&mut self, key: RedisKey, entry: Entry) {
+        match entry.expires_at {
+            Some(expires_at) => self.expiring.insert(&key, expires_at),
+            None => self.expiring.remove(&key),
+        }
+        match &entry.value {
+            Value::Hash(hash) if hash.has_expirations() => self
+                .expiring_fields
+                .insert(&key, hash.next_expiration().unwrap()),
+            _ => self.expiring_fields.remove(&key),
+        }
+        self.wake_waiters(&key);
+        self.insert_entry(key, entry);
+    }
+
+    /// Notify a client when a key is created
+    pub fn add_waiter(&mut self, key: &RedisKey, id: u64, ready: Arc<Notify>) {
+        self.waiters
+            .entry(key.clone())
+            .or_default()
+            .push((id, ready));
+    }
+
+    pub fn remove_waiter(&mut self, key: &RedisKey, id: u64) {
+        if let Some(waiters) = self.waiters.get_mut(key) {
+            waiters.retain(|(waiter, _)| *waiter != id);
+            if waiters.is_empty() {
+                self.waiters.remove(key);
+            }
+        }
+    }
+
+    /// Notify the clients waiting for a key, they retry their commands and the ones still
+    /// waiting are added again
+    /// Called when a key is created, and by commands adding to a value other commands wait for
+    pub fn wake_waiters(&mut self, key: &RedisKey) {
+        if self.waiters.is_empty() {
+            return;
+        }
+        for (_, ready) in self.waiters.remove(key).unwrap_or_default() {
+            ready.notify();
+        }
+    }
+
+    /// Swap the contents with another database, blocked clients stay in their database and are
+    /// notified to check the new contents
+    pub fn swap(&mut self, other: &mut Storage) {
+        std::mem::swap(self, other);
+        std::mem::swap(&mut self.waiters, &mut other.waiters);
+        for storage in &mut [self, other] {
+            for (_, waiters) in storage.waiters.drain() {
+                waiters.iter().for_each(|(_, ready)| ready.notify());
+            }
+        }
+    }
+
+    /// Take the contents leaving the database empty, blocked clients stay in it
+    pub fn take(&mut self) -> Storage {
+        let mut old = std::mem::take(self);
+        std::mem::swap(&mut self.waiters, &mut old.waiters);
+        old
+    }
+
+    /// Remove a key, returns its entry if it was not expired
+    pub fn remove(&mut self, key: &RedisKey) -> Option<Entry> {
+        if self.expire_if_needed(key) {
+            return None;
+        }
+        self.remove_entry(key)
+    }
+
+    pub fn contains_key(&mut self, key: &RedisKey) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// Remove all the expired keys and hash fields
+    fn purge_expired(&mut self) {
+        self.expire_due(usize::MAX);
+    }
+
+    /// All the keys, removing the expired keys found
+    pub fn keys(&mut self) -> impl Iterator<Item = &RedisKey> {
+        self.purge_expired();
+        self.entries.keys()
+    }
+
+    /// Number of keys, excluding expired keys
+    pub fn count(&mut self) -> usize {
+        self.purge_expired();
+        self.entries.len()
+    }
+
+    /// A random key, every key has the same probability
+    pub fn random_key(&mut self) -> Option<RedisKey> {
+        while let Some(key) = self.keys.random().cloned() {
+            if !self.expire_if_needed(&key) {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// Number of keys stored, including expired keys that were not removed yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of keys with an expiration, including expired keys that were not removed yet
+    pub fn expires(&self) -> usize {
+        self.expiring.by_key.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.keys.clear();
+        self.expiring.clear();
+        self.expiring_fields.clear();
+    }
+
+    /// Change the expiration of a key, returns false if the key doesn't exist
+    pub fn set_expiration(&mut self, key: &RedisKey, expires_at: Option<i64>) -> bool {
+        let entry = match self.get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        entry.expires_at = expires_at;
+        // A deadline in the past removes the key right away
+        if !self.expire_if_needed(key) {
+            match expires_at {
+                Some(expires_at) => self.expiring.insert(key, expires_at),
+                None => self.expiring.remove(key),
+            }
+        }
+        true
+    }
+
+    /// Active expiration, remove up to `limit` expired keys and the expired fields of up to
+    /// `limit` hashes, taken in the order they expire so only expired ones are visited
+    /// Returns the most keys or hashes handled, if it's below `limit` nothing else expired
+    pub fn expire_due(&mut self, limit: usize) -> usize {
+        let now = now_ms();
+        let mut removed = 0;
+        while removed < limit {
+            let key = match self.expiring.first_due(now) {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            self.remove_entry(&key);
+            removed += 1;
+        }
+        let mut hashes = 0;
+        while hashes < limit {
+            let key = match self.expiring_fields.first_due(now) {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            self.expire_fields(&key, now);
+            hashes += 1;
+        }
+        removed.max(hashes)
+    }
+}
+
+/// Numbered databases of the server, every client picks one with SELECT
+/// Each database has its own lock
+pub struct Databases(Vec<Mutex<Storage>>);
+
+impl Databases {
+    pub fn new(count: usize) -> Databases {
+        Databases((0..count).map(|_| Mutex::new(Storage::new())).collect())
+    }
+
+    /// Database by index, indexes are checked by SELECT so it must exist
+    pub fn select(&self, index: usize) -> &Mutex<Storage> {
+        &self.0[index]
+    }
+
+    /// Lock two different databases, always in the same order so two clients locking the same
+    /// pair can't deadlock
+    pub fn lock_pair(
+        &self,
+        first: usize,
+        second: usize,
+    ) -> (MutexGuard<'_, Storage>, MutexGuard<'_, Storage>) {
+        if first < second {
+            let first = self.0[first].lock().unwrap();
+            (first, self.0[second].lock().unwrap())
+        } else {
+            let second = self.0[second].lock().unwrap();
+            (self.0[first].lock().unwrap(), second)
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mutex<Storage>> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
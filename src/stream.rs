@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+use crate::storage::{now_ms, Storage};
+use crate::types::{BulkString, RedisKey, RespValue, XAddOptions};
+use crate::value::{Stream, StreamId, Value};
+
+/// Id of a new entry of XADD
+#[derive(Debug, Clone, Copy)]
+pub enum NewId {
+    /// `*`, the current time and the next sequence number
+    Auto,
+    /// `ms-*`, the next sequence number of the time
+    Sequence(u64),
+    Explicit(StreamId),
+}
+
+/// Entries to remove from a stream after adding one
+#[derive(Debug, Clone, Copy)]
+pub enum Trim {
+    /// Keep at most this many entries
+    MaxLen(usize),
+    /// Remove the entries with lower ids
+    MinId(StreamId),
+}
+
+/// Stream id formatted as a reply, `ms-seq`
+fn id_reply((ms, seq): StreamId) -> RespValue {
+    RespValue::BulkString(BulkString(format!("{}-{}", ms, seq).into_bytes()))
+}
+
+/// Entries formatted as a reply, every one is its id and its field-value pairs
+pub fn entries_reply<'a>(
+    entries: impl Iterator<Item = (&'a StreamId, &'a Vec<(BulkString, BulkString)>)>,
+) -> RespValue {
+    let entries = entries
+        .map(|(id, fields)| {
+            let fields = fields
+                .iter()
+                .flat_map(|(field, value)| vec![field.clone(), value.clone()])
+                .map(RespValue::BulkString)
+                .collect();
+            RespValue::Array(vec![id_reply(*id), RespValue::Array(fields)].into())
+        })
+        .collect();
+    RespValue::Array(entries)
+}
+
+/// Resolve the id of a new entry, it has to be greater than the last one of the stream
+fn next_id(stream: &Stream, id: NewId) -> Result<StreamId, &'static str> {
+    let (last_ms, last_seq) = stream.last_id();
+    let next = |ms: u64| match ms.cmp(&last_ms) {
+        std::cmp::Ordering::Greater => Ok((ms, 0)),
+        std::cmp::Ordering::Equal => match last_seq.checked_add(1) {
+            Some(seq) => Ok((ms, seq)),
+            None => Err(SMALLER_ID),
+        },
+        std::cmp::Ordering::Less => Err(SMALLER_ID),
+    };
+    match id {
+        // The time is not allowed to go back, the last one is used with the next sequence
+        NewId::Auto => match next((now_ms() as u64).max(last_ms)) {
+            Ok(id) => Ok(id),
+            Err(_) => match last_ms.checked_add(1) {
+                Some(ms) => Ok((ms, 0)),
+                None => Err(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items",
+                ),
+            },
+        },
+        NewId::Sequence(ms) => next(ms),
+        NewId::Explicit((0, 0)) => Err("ERR The ID specified in XADD must be greater than 0-0"),
+        NewId::Explicit(id) if id <= stream.last_id() => Err(SMALLER_ID),
+        NewId::Explicit(id) => Ok(id),
+    }
+}
+
+/// Error replied when the id of a new entry is not greater than the last one
+const SMALLER_ID: &str =
+    "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+
+/// Append an entry to a stream, creating it unless NOMKSTREAM is used, then trim it
+/// Replies the id of the entry, or null if the stream doesn't exist with NOMKSTREAM
+pub fn xadd(
+    storage: &mut Storage,
+    key: &RedisKey,
+    options: &XAddOptions,
+    id: NewId,
+    fields: Vec<(BulkString, BulkString)>,
+) -> Result<RespValue, &'static str> {
+    if options.no_mkstream && storage.get(key).is_none() {
+        return Ok(RespValue::Null);
+    }
+    let mut created = None;
+    let stream = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_stream_mut()?,
+        None => created.get_or_insert_with(Stream::default),
+    };
+    let id = next_id(stream, id)?;
+    stream.insert(id, fields);
+    match options.trim {
+        Some(Trim::MaxLen(max_len)) => {
+            while stream.len() > max_len {
+                stream.pop_first();
+            }
+        }
+        Some(Trim::MinId(min_id)) => {
+            while stream.keys().next().is_some_and(|first| *first < min_id) {
+                stream.pop_first();
+            }
+        }
+        None => {}
+    }
+    match created {
+        Some(stream) => storage.set(key.clone(), Value::Stream(stream)),
+        // Clients blocked in XREAD wait for new entries, not only for the stream
+        None => storage.wake_waiters(key),
+    }
+    Ok(id_reply(id))
+}
+
+/// Number of entries of a stream
+pub fn xlen(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry.value.as_stream()?.len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Entries with ids between `start` and `end` inclusive, up to `count` of them, from the last
+/// one with `rev`
+pub fn xrange(
+    storage: &mut Storage,
+    key: &RedisKey,
+    start: StreamId,
+    end: StreamId,
+    count: Option<usize>,
+    rev: bool,
+) -> Result<RespValue, &'static str> {
+    let stream = match storage.get(key) {
+        Some(entry) => entry.value.as_stream()?,
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    if start > end {
+        return Ok(RespValue::Array(VecDeque::new()));
+    }
+    // Like redis, COUNT 0 replies a null array
+    let count = match count {
+        Some(0) => return Ok(RespValue::NullArray),
+        Some(count) => count,
+        None => usize::MAX,
+    };
+    let entries = stream.range(start..=end);
+    if rev {
+        Ok(entries_reply(entries.rev().take(count)))
+    } else {
+        Ok(entries_reply(entries.take(count)))
+    }
+}
+
+/// Resolve the ids of XREAD, `$` (`None`) is the last id of the stream, 0-0 if it doesn't exist
+pub fn resolve_ids(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    ids: &[Option<StreamId>],
+) -> Result<Vec<StreamId>, &'static str> {
+    keys.iter()
+        .zip(ids)
+        .map(|(key, id)| match id {
+            Some(id) => Ok(*id),
+            None => match storage.get(key) {
+                Some(entry) => Ok(entry.value.as_stream()?.last_id()),
+                None => Ok((0, 0)),
+            },
+        })
+        .collect()
+}
+
+/// Entries of several streams with ids greater than the ones given, up to `count` for every
+/// stream
+/// Replies the key and the entries of the streams with new entries, or a null array if there are
+/// none
+pub fn xread(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    ids: &[StreamId],
+    count: Option<usize>,
+) -> Result<RespValue, &'static str> {
+    let count = count.filter(|count| *count > 0).unwrap_or(usize::MAX);
+    let mut streams = VecDeque::new();
+    for ((key, id), entry) in keys.iter().zip(ids).zip(storage.get_many(keys)) {
+        let stream = match entry {
+            Some(entry) => entry.value.as_stream()?,
+            None => continue,
+        };
+        let mut entries = stream
+            .range((Bound::Excluded(*id), Bound::Unbounded))
+            .peekable();
+        if entries.peek().is_none() {
+            continue;
+        }
+        let reply = vec![
+            RespValue::BulkString(key.clone()),
+            entries_reply(entries.take(count)),
+        ];
+        streams.push_back(RespValue::Array(reply.into()));
+    }
+    if streams.is_empty() {
+        return Ok(RespValue::NullArray);
+    }
+    Ok(RespValue::Array(streams))
+}
@@ -1,9 +1,28 @@
-use std::collections::{HashMap, VecDeque};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::ops::Bound;
+use std::sync::Mutex;
+use std::time::Duration;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+use crate::bitmap;
+use crate::client::Client;
+#[cfg(feature = "failpoints")]
+use crate::failpoints::{self, Failpoint};
+use crate::geo;
+use crate::hash;
+use crate::hyperloglog;
+use crate::list::{self, Side};
+use crate::random;
+use crate::rdb;
+use crate::set;
+use crate::storage::{now_ms, Databases, Storage};
+use crate::stream::{self, NewId, Trim};
+use crate::value::{StreamId, Value};
+use crate::zset;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BulkString(pub Vec<u8>);
 
 impl BulkString {
@@ -31,19 +50,22 @@ impl fmt::Debug for BulkString {
 
 #[derive(Debug, Clone)]
 pub enum RespValue {
-    SimpleString(String),
+    /// Borrowed for static replies (ie. OK) so they don't need an allocation
+    SimpleString(Cow<'static, str>),
     Error(String, Option<String>),
     Integer(i64),
     BulkString(BulkString),
     Array(VecDeque<RespValue>),
     Null,
+    /// Null reply of commands replying arrays, ie. when a blocking command times out
+    NullArray,
 }
 
 impl RespValue {
     fn to_string(&self) -> Option<String> {
         use RespValue::*;
         match self {
-            SimpleString(ref value) => Some(value.clone()),
+            SimpleString(ref value) => Some(value.to_string()),
             BulkString(ref value) => String::from_utf8(value.0.to_vec()).ok(),
             _ => None,
         }
@@ -59,45 +81,942 @@ impl RespValue {
     // }
 }
 
+/// Error replied when an argument or a stored value is not a valid i64
+const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+
+/// Longest string SETRANGE can create, like redis
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Error replied for an integer type of BITFIELD other than i1 to i64 or u1 to u63
+const BITFIELD_TYPE: &str =
+    "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.";
+
+/// Errors replied when GEOSEARCH has none or both of the centers or the shapes
+const GEO_FROM: &str = "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH";
+const GEO_BY: &str = "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH";
+
+/// Error replied for a malformed stream id
+const INVALID_STREAM_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Error replied when an argument or a stored value is not a valid float
+const NOT_FLOAT: &str = "ERR value is not a valid float";
+
 pub type RedisKey = BulkString;
+/// Value sent by the client, stored as `Value::String`
 pub type RedisValue = BulkString;
 
+/// Expiration of a key in milliseconds, relative to now or as a unix time
+#[derive(Debug, Clone, Copy)]
+pub enum Expiration {
+    In(i64),
+    At(i64),
+}
+
+impl Expiration {
+    /// Parse the time after an EX/PX/EXAT/PXAT option
+    fn parse(option: &str, resp: &mut VecDeque<RespValue>) -> Result<Expiration, &'static str> {
+        Ok(match option {
+            "EX" => Expiration::In(get_next_seconds(resp)?),
+            "PX" => Expiration::In(get_next_integer(resp)?),
+            "EXAT" => Expiration::At(get_next_seconds(resp)?),
+            _ => Expiration::At(get_next_integer(resp)?),
+        })
+    }
+
+    /// Options of SET and GETEX only accept positive times
+    fn is_positive(self) -> bool {
+        match self {
+            Expiration::In(millis) | Expiration::At(millis) => millis > 0,
+        }
+    }
+
+    /// Unix time in milliseconds when the key expires
+    fn deadline(self, now: i64) -> i64 {
+        match self {
+            Expiration::In(millis) => now.saturating_add(millis),
+            Expiration::At(millis) => millis,
+        }
+    }
+}
+
+/// Options of SET
+#[derive(Debug, Default)]
+pub struct SetOptions {
+    pub expiration: Option<Expiration>,
+    /// NX (`Some(false)`, the key must not exist) or XX (`Some(true)`, the key must exist)
+    pub exists: Option<bool>,
+    pub keep_ttl: bool,
+    /// Reply with the previous value
+    pub get: bool,
+}
+
+impl SetOptions {
+    /// Parse the options after the key and value, each option can only appear once and
+    /// conflicting options (ie. NX and XX, EX and KEEPTTL) are a syntax error
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<SetOptions, &'static str> {
+        let mut options = SetOptions::default();
+        while !resp.is_empty() {
+            let option = get_next_value(resp)?.to_string().to_uppercase();
+            match option.as_ref() {
+                "NX" | "XX" if options.exists.is_none() => options.exists = Some(option == "XX"),
+                "KEEPTTL" if !options.keep_ttl && options.expiration.is_none() => {
+                    options.keep_ttl = true
+                }
+                "GET" if !options.get => options.get = true,
+                "EX" | "PX" | "EXAT" | "PXAT"
+                    if !options.keep_ttl && options.expiration.is_none() =>
+                {
+                    let expiration = Expiration::parse(&option, resp)?;
+                    if !expiration.is_positive() {
+                        return Err("ERR invalid expire time in 'set' command");
+                    }
+                    options.expiration = Some(expiration);
+                }
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Conditions of EXPIRE and its variants, the expiration is only changed when they are met
+#[derive(Debug, Default)]
+pub struct ExpireOptions {
+    /// The key has no expiration
+    pub nx: bool,
+    /// The key has an expiration
+    pub xx: bool,
+    /// The new expiration is later than the current one, no expiration counts as infinite
+    pub gt: bool,
+    /// The new expiration is sooner than the current one
+    pub lt: bool,
+}
+
+impl ExpireOptions {
+    /// Parse the conditions after the time, up to the end of the arguments or up to FIELDS for
+    /// the hash field commands
+    fn parse(
+        resp: &mut VecDeque<RespValue>,
+        before_fields: bool,
+    ) -> Result<ExpireOptions, &'static str> {
+        let mut options = ExpireOptions::default();
+        while !resp.is_empty() {
+            let flag = get_next_value(resp)?.to_string().to_uppercase();
+            match flag.as_ref() {
+                "FIELDS" if before_fields => {
+                    resp.push_front(RespValue::BulkString(BulkString(flag.into_bytes())));
+                    break;
+                }
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                _ => return Err("ERR Unsupported option"),
+            }
+        }
+        if options.nx && (options.xx || options.gt || options.lt) {
+            return Err("ERR NX and XX, GT or LT options at the same time are not compatible");
+        }
+        if options.gt && options.lt {
+            return Err("ERR GT and LT options at the same time are not compatible");
+        }
+        Ok(options)
+    }
+
+    /// Check the conditions against the current expiration of the key
+    pub fn allows(&self, current: Option<i64>, expires_at: i64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => {
+                !self.nx && (!self.gt || expires_at > current) && (!self.lt || expires_at < current)
+            }
+        }
+    }
+}
+
+/// Options of ZADD
+#[derive(Debug, Default)]
+pub struct ZAddOptions {
+    /// Only add new members
+    pub nx: bool,
+    /// Only update existing members
+    pub xx: bool,
+    /// Only update members when the new score is greater, new members are still added
+    pub gt: bool,
+    /// Only update members when the new score is lower
+    pub lt: bool,
+    /// Reply the number of members added or updated instead of only the added ones
+    pub ch: bool,
+    /// Increment the score of the member, replying the new score
+    pub incr: bool,
+}
+
+impl ZAddOptions {
+    /// Parse the flags before the scores and members
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<ZAddOptions, &'static str> {
+        let mut options = ZAddOptions::default();
+        while let Some(RespValue::BulkString(flag)) = resp.front() {
+            match flag.to_string().to_uppercase().as_ref() {
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                "CH" => options.ch = true,
+                "INCR" => options.incr = true,
+                _ => break,
+            }
+            resp.pop_front();
+        }
+        if options.nx && options.xx {
+            return Err("ERR XX and NX options at the same time are not compatible");
+        }
+        if (options.gt && options.lt) || (options.nx && (options.gt || options.lt)) {
+            return Err("ERR GT, LT, and/or NX options at the same time are not compatible");
+        }
+        Ok(options)
+    }
+
+    /// Check the conditions against the current score of the member
+    pub fn allows(&self, current: Option<f64>, score: f64) -> bool {
+        match current {
+            None => !self.xx,
+            Some(current) => {
+                !self.nx && (!self.gt || score > current) && (!self.lt || score < current)
+            }
+        }
+    }
+}
+
+/// Members selected by ZRANGE
+#[derive(Debug)]
+pub enum ZRangeBy {
+    /// Inclusive ranks, negative ones start from the end
+    Rank(i64, i64),
+    /// Lowest and highest scores
+    Score(Bound<f64>, Bound<f64>),
+    /// Lowest and highest members, for sorted sets where all the scores are the same
+    Lex(Bound<BulkString>, Bound<BulkString>),
+}
+
+/// Options of ZRANGE and its older variants
+#[derive(Debug)]
+pub struct ZRangeOptions {
+    pub by: ZRangeBy,
+    /// From the highest score
+    pub rev: bool,
+    /// Offset and count of the members selected, a negative count selects all of them
+    pub limit: Option<(i64, i64)>,
+    pub with_scores: bool,
+}
+
+impl ZRangeOptions {
+    /// Parse the range and the options of ZRANGE, or the ones of the variant `command`
+    /// ZRANGESTORE takes the same options except WITHSCORES
+    fn parse(command: &str, resp: &mut VecDeque<RespValue>) -> Result<ZRangeOptions, &'static str> {
+        let start = get_next_value(resp)?;
+        let end = get_next_value(resp)?;
+        let mut by_score = command.ends_with("BYSCORE");
+        let mut by_lex = command.ends_with("BYLEX");
+        let mut rev = command.starts_with("ZREV");
+        let mut limit = None;
+        let mut with_scores = false;
+        let unified = command == "ZRANGE" || command == "ZRANGESTORE";
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "WITHSCORES" if command != "ZRANGESTORE" => with_scores = true,
+                "LIMIT" if command != "ZREVRANGE" => {
+                    limit = Some((get_next_integer(resp)?, get_next_integer(resp)?))
+                }
+                "BYSCORE" if unified => by_score = true,
+                "BYLEX" if unified => by_lex = true,
+                "REV" if unified => rev = true,
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        if by_score && by_lex {
+            return Err("ERR syntax error");
+        }
+        if limit.is_some() && !by_score && !by_lex {
+            return Err("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX");
+        }
+        if with_scores && by_lex {
+            return Err("ERR syntax error, WITHSCORES not supported in combination with BYLEX");
+        }
+        // Reversed ranges of scores and members start from the highest
+        let (min, max) = if rev && (by_score || by_lex) {
+            (end, start)
+        } else {
+            (start, end)
+        };
+        let by = if by_score {
+            ZRangeBy::Score(parse_score_bound(&min)?, parse_score_bound(&max)?)
+        } else if by_lex {
+            ZRangeBy::Lex(parse_lex_bound(min)?, parse_lex_bound(max)?)
+        } else {
+            let rank = |value: BulkString| value.to_string().parse().map_err(|_| NOT_INTEGER);
+            ZRangeBy::Rank(rank(min)?, rank(max)?)
+        };
+        Ok(ZRangeOptions {
+            by,
+            rev,
+            limit,
+            with_scores,
+        })
+    }
+}
+
+/// Options of ZUNION, ZINTER and ZDIFF and their STORE variants
+#[derive(Debug)]
+pub struct ZCombineOptions {
+    /// Multiplier of the scores of every key
+    pub weights: Option<Vec<f64>>,
+    pub aggregate: zset::Aggregate,
+    pub with_scores: bool,
+}
+
+impl ZCombineOptions {
+    /// Parse the options after the keys, ZDIFF has no WEIGHTS nor AGGREGATE and the STORE
+    /// variants have no WITHSCORES
+    fn parse(
+        resp: &mut VecDeque<RespValue>,
+        keys: usize,
+        operation: set::Operation,
+        store: bool,
+    ) -> Result<ZCombineOptions, &'static str> {
+        let mut options = ZCombineOptions {
+            weights: None,
+            aggregate: zset::Aggregate::Sum,
+            with_scores: false,
+        };
+        let aggregates = !matches!(operation, set::Operation::Diff);
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "WEIGHTS" if aggregates && resp.len() >= keys => {
+                    let weights = resp
+                        .drain(..keys)
+                        .map(|weight| match weight {
+                            RespValue::BulkString(weight) => parse_float(&weight.to_string())
+                                .map_err(|_| "ERR weight value is not a float"),
+                            _ => Err("ERR weight value is not a float"),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    options.weights = Some(weights);
+                }
+                "AGGREGATE" if aggregates => {
+                    options.aggregate =
+                        match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                            "SUM" => zset::Aggregate::Sum,
+                            "MIN" => zset::Aggregate::Min,
+                            "MAX" => zset::Aggregate::Max,
+                            _ => return Err("ERR syntax error"),
+                        }
+                }
+                "WITHSCORES" if !store => options.with_scores = true,
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Center of GEOSEARCH
+#[derive(Debug)]
+pub enum GeoFrom {
+    Member(BulkString),
+    /// Longitude and latitude
+    LonLat(f64, f64),
+}
+
+/// Area of GEOSEARCH around the center, sizes in meters
+#[derive(Debug)]
+pub enum GeoShape {
+    Radius(f64),
+    /// Width and height
+    Box(f64, f64),
+}
+
+/// Options of GEOSEARCH and GEOSEARCHSTORE
+#[derive(Debug)]
+pub struct GeoSearchOptions {
+    pub from: GeoFrom,
+    pub shape: GeoShape,
+    /// Length of the unit of the shape in meters, distances are replied in it
+    pub unit: f64,
+    pub asc: bool,
+    pub desc: bool,
+    pub count: Option<usize>,
+    /// Stop at the first `count` members found instead of the closest ones
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+    /// Store the distances as scores instead of the positions
+    pub store_dist: bool,
+}
+
+impl GeoSearchOptions {
+    /// Parse the options after the key, the STORE variant has STOREDIST instead of the WITH
+    /// options
+    fn parse(
+        resp: &mut VecDeque<RespValue>,
+        store: bool,
+    ) -> Result<GeoSearchOptions, &'static str> {
+        let (mut from, mut shape, mut unit) = (None, None, 1.0);
+        let (mut asc, mut desc, mut count, mut any) = (false, false, None, false);
+        let (mut with_coord, mut with_dist, mut with_hash, mut store_dist) =
+            (false, false, false, false);
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "FROMMEMBER" if from.is_none() => {
+                    from = Some(GeoFrom::Member(get_next_value(resp)?))
+                }
+                "FROMLONLAT" if from.is_none() => {
+                    let longitude = parse_score(&get_next_value(resp)?)?;
+                    let latitude = parse_score(&get_next_value(resp)?)?;
+                    geo::encode(longitude, latitude)?;
+                    from = Some(GeoFrom::LonLat(longitude, latitude));
+                }
+                "FROMMEMBER" | "FROMLONLAT" => return Err(GEO_FROM),
+                "BYRADIUS" if shape.is_none() => {
+                    let radius = parse_score(&get_next_value(resp)?)?;
+                    if radius < 0.0 {
+                        return Err("ERR radius cannot be negative");
+                    }
+                    unit = parse_unit(&get_next_value(resp)?)?;
+                    shape = Some(GeoShape::Radius(radius * unit));
+                }
+                "BYBOX" if shape.is_none() => {
+                    let width = parse_score(&get_next_value(resp)?)?;
+                    let height = parse_score(&get_next_value(resp)?)?;
+                    if width < 0.0 || height < 0.0 {
+                        return Err("ERR height or width cannot be negative");
+                    }
+                    unit = parse_unit(&get_next_value(resp)?)?;
+                    shape = Some(GeoShape::Box(width * unit, height * unit));
+                }
+                "BYRADIUS" | "BYBOX" => return Err(GEO_BY),
+                // The last order wins, like in redis
+                "ASC" => {
+                    asc = true;
+                    desc = false;
+                }
+                "DESC" => {
+                    asc = false;
+                    desc = true;
+                }
+                "COUNT" => {
+                    count = match get_next_integer(resp)? {
+                        count if count > 0 => Some(count as usize),
+                        _ => return Err("ERR COUNT must be > 0"),
+                    };
+                    if let Some(RespValue::BulkString(next)) = resp.front() {
+                        if next.to_string().to_uppercase() == "ANY" {
+                            resp.pop_front();
+                            any = true;
+                        }
+                    }
+                }
+                "ANY" => return Err("ERR the ANY argument requires COUNT argument"),
+                "WITHCOORD" if !store => with_coord = true,
+                "WITHDIST" if !store => with_dist = true,
+                "WITHHASH" if !store => with_hash = true,
+                "STOREDIST" if store => store_dist = true,
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(GeoSearchOptions {
+            from: from.ok_or(GEO_FROM)?,
+            shape: shape.ok_or(GEO_BY)?,
+            unit,
+            asc,
+            desc,
+            count,
+            any,
+            with_coord,
+            with_dist,
+            with_hash,
+            store_dist,
+        })
+    }
+}
+
+/// Options of XADD before the id
+#[derive(Debug, Default)]
+pub struct XAddOptions {
+    /// Don't create the stream if it doesn't exist
+    pub no_mkstream: bool,
+    pub trim: Option<Trim>,
+}
+
+impl XAddOptions {
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<XAddOptions, &'static str> {
+        let mut options = XAddOptions::default();
+        let mut approximate = false;
+        let mut limit = false;
+        while let Some(RespValue::BulkString(option)) = resp.front() {
+            match option.to_string().to_uppercase().as_ref() {
+                "NOMKSTREAM" => options.no_mkstream = true,
+                option @ "MAXLEN" | option @ "MINID" => {
+                    let max_len = option == "MAXLEN";
+                    resp.pop_front();
+                    let mut threshold = get_next_value(resp)?;
+                    // Trimming is always exact, approximate trimming is allowed to remove more
+                    if threshold.0 == b"=" || threshold.0 == b"~" {
+                        approximate = threshold.0 == b"~";
+                        threshold = get_next_value(resp)?;
+                    }
+                    options.trim = Some(if max_len {
+                        match threshold.to_string().parse::<i64>() {
+                            Ok(max_len) if max_len >= 0 => Trim::MaxLen(max_len as usize),
+                            Ok(_) => return Err("ERR The MAXLEN argument must be >= 0."),
+                            Err(_) => return Err(NOT_INTEGER),
+                        }
+                    } else {
+                        Trim::MinId(parse_stream_id(&threshold.to_string(), 0)?)
+                    });
+                    continue;
+                }
+                "LIMIT" => {
+                    resp.pop_front();
+                    if get_next_integer(resp)? < 0 {
+                        return Err("ERR The LIMIT argument must be >= 0.");
+                    }
+                    limit = true;
+                    continue;
+                }
+                _ => break,
+            }
+            resp.pop_front();
+        }
+        if limit && !approximate {
+            return Err("ERR syntax error, LIMIT cannot be used without the special ~ option");
+        }
+        Ok(options)
+    }
+}
+
+/// Options of the SCAN family
+#[derive(Debug)]
+pub struct ScanOptions {
+    /// Only reply elements matching the glob pattern
+    pub pattern: Option<BulkString>,
+    /// Elements visited by every call, the reply can have less of them after filtering
+    pub count: usize,
+    /// Reply only the fields of a hash
+    pub no_values: bool,
+}
+
+impl ScanOptions {
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<ScanOptions, &'static str> {
+        let mut options = ScanOptions {
+            pattern: None,
+            count: 10,
+            no_values: false,
+        };
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "MATCH" => options.pattern = Some(get_next_value(resp)?),
+                "COUNT" => match get_next_integer(resp)? {
+                    count if count >= 1 => options.count = count as usize,
+                    _ => return Err("ERR syntax error"),
+                },
+                "NOVALUES" => options.no_values = true,
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Options of LPOS
+#[derive(Debug)]
+pub struct LPosOptions {
+    /// Skip the first matches, or search from the tail if it's negative
+    pub rank: i64,
+    /// Reply an array with up to this many positions, 0 for all of them
+    pub count: Option<usize>,
+    /// Compare only this many elements, 0 for the whole list
+    pub max_len: usize,
+}
+
+impl LPosOptions {
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<LPosOptions, &'static str> {
+        let mut options = LPosOptions {
+            rank: 1,
+            count: None,
+            max_len: 0,
+        };
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "RANK" => match get_next_integer(resp)? {
+                    0 => return Err("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list"),
+                    rank if rank == i64::MIN => return Err("ERR value is out of range"),
+                    rank => options.rank = rank,
+                },
+                "COUNT" => match get_next_integer(resp)? {
+                    count if count >= 0 => options.count = Some(count as usize),
+                    _ => return Err("ERR COUNT can't be negative"),
+                },
+                "MAXLEN" => match get_next_integer(resp)? {
+                    max_len if max_len >= 0 => options.max_len = max_len as usize,
+                    _ => return Err("ERR MAXLEN can't be negative"),
+                },
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Range of BITCOUNT and BITPOS, the whole string by default
+#[derive(Debug)]
+pub struct BitRange {
+    pub start: i64,
+    /// Inclusive, negative indexes start from the end
+    pub end: Option<i64>,
+    /// Indexes are bits instead of bytes
+    pub bits: bool,
+}
+
+impl BitRange {
+    /// Parse `[start [end [BYTE|BIT]]]`, BITCOUNT needs both start and end with `end_required`
+    fn parse(resp: &mut VecDeque<RespValue>, end_required: bool) -> Result<BitRange, &'static str> {
+        let mut range = BitRange {
+            start: 0,
+            end: None,
+            bits: false,
+        };
+        if resp.is_empty() {
+            return Ok(range);
+        }
+        range.start = get_next_integer(resp)?;
+        if resp.is_empty() {
+            if end_required {
+                return Err("ERR syntax error");
+            }
+            return Ok(range);
+        }
+        range.end = Some(get_next_integer(resp)?);
+        if !resp.is_empty() {
+            range.bits = match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "BYTE" => false,
+                "BIT" => true,
+                _ => return Err("ERR syntax error"),
+            };
+        }
+        if !resp.is_empty() {
+            return Err("ERR syntax error");
+        }
+        Ok(range)
+    }
+}
+
+/// Options of RESTORE
+#[derive(Debug, Default)]
+pub struct RestoreOptions {
+    pub replace: bool,
+    /// The TTL is a unix time in milliseconds
+    pub abs_ttl: bool,
+    /// Seconds since the last access
+    pub idle_time: Option<i64>,
+}
+
+impl RestoreOptions {
+    fn parse(resp: &mut VecDeque<RespValue>) -> Result<RestoreOptions, &'static str> {
+        let mut options = RestoreOptions::default();
+        while !resp.is_empty() {
+            match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                "REPLACE" => options.replace = true,
+                "ABSTTL" => options.abs_ttl = true,
+                "IDLETIME" => match get_next_integer(resp)? {
+                    idle_time if idle_time >= 0 => options.idle_time = Some(idle_time),
+                    _ => return Err("ERR Invalid IDLETIME value, must be >= 0"),
+                },
+                // Accepted for compatibility, there is no LFU counter
+                "FREQ" => match get_next_integer(resp)? {
+                    0..=255 => {}
+                    _ => return Err("ERR Invalid FREQ value, must be >= 0 and <= 255"),
+                },
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Subcommands of OBJECT
+#[derive(Debug)]
+pub enum ObjectSubcommand {
+    Encoding,
+    RefCount,
+    IdleTime,
+    Freq,
+}
+
 #[derive(Debug)]
 pub enum RedisCmd {
     Ping(Option<RedisValue>),
     Get(RedisKey),
+    GetDel(RedisKey),
+    /// `None` keeps the TTL, `Some(None)` is the PERSIST option
+    GetEx(RedisKey, Option<Option<Expiration>>),
     Delete(Vec<RedisKey>),
-    Set(RedisKey, RedisValue),
+    Unlink(Vec<RedisKey>),
+    Set(RedisKey, RedisValue, SetOptions),
+    MGet(Vec<RedisKey>),
+    MSet(Vec<(RedisKey, RedisValue)>),
+    MSetNx(Vec<(RedisKey, RedisValue)>),
     Append(RedisKey, RedisValue),
+    StrLen(RedisKey),
+    GetRange(RedisKey, i64, i64),
+    SetRange(RedisKey, i64, RedisValue),
+    SetBit(RedisKey, usize, bool),
+    GetBit(RedisKey, usize),
+    BitCount(RedisKey, BitRange),
+    /// Key, bit searched and range
+    BitPos(RedisKey, bool, BitRange),
+    /// Operation, destination and keys
+    BitOp(bitmap::Operation, RedisKey, Vec<RedisKey>),
+    BitField(RedisKey, Vec<bitmap::BitFieldOp>),
+    PfAdd(RedisKey, Vec<RedisValue>),
+    PfCount(Vec<RedisKey>),
+    /// Destination and sources
+    PfMerge(RedisKey, Vec<RedisKey>),
+    IncrBy(RedisKey, i64),
+    IncrByFloat(RedisKey, f64),
     Keys(RedisValue),
     Exists(RedisKey),
-    FlushAll,
+    Dump(RedisKey),
+    Object(ObjectSubcommand, RedisKey),
+    /// Key, TTL in milliseconds (0 for no TTL), payload created by DUMP
+    Restore(RedisKey, i64, BulkString, RestoreOptions),
+    Touch(Vec<RedisKey>),
+    RandomKey,
+    DbSize,
+    Expire(RedisKey, Expiration, ExpireOptions),
+    Ttl(RedisKey),
+    PTtl(RedisKey),
+    ExpireTime(RedisKey),
+    PExpireTime(RedisKey),
+    Persist(RedisKey),
+    HSet(RedisKey, Vec<(RedisValue, RedisValue)>),
+    HSetNx(RedisKey, RedisValue, RedisValue),
+    HGet(RedisKey, RedisValue),
+    HStrLen(RedisKey, RedisValue),
+    /// Deprecated version of HSET replying OK
+    HMSet(RedisKey, Vec<(RedisValue, RedisValue)>),
+    HMGet(RedisKey, Vec<RedisValue>),
+    HDel(RedisKey, Vec<RedisValue>),
+    HGetAll(RedisKey),
+    HKeys(RedisKey),
+    HVals(RedisKey),
+    HLen(RedisKey),
+    HExists(RedisKey, RedisValue),
+    HScan(RedisKey, u64, ScanOptions),
+    /// Key, count and WITHVALUES
+    HRandField(RedisKey, Option<i64>, bool),
+    HExpire(RedisKey, Expiration, ExpireOptions, Vec<RedisValue>),
+    HTtl(RedisKey, Vec<RedisValue>),
+    HPTtl(RedisKey, Vec<RedisValue>),
+    HPersist(RedisKey, Vec<RedisValue>),
+    /// Key, end of the list, only if the list exists and elements
+    Push(RedisKey, Side, bool, Vec<RedisValue>),
+    /// Key, end of the list and number of elements, replied in an array
+    Pop(RedisKey, Side, Option<usize>),
+    LLen(RedisKey),
+    LRange(RedisKey, i64, i64),
+    LIndex(RedisKey, i64),
+    LSet(RedisKey, i64, RedisValue),
+    /// Key, BEFORE, pivot and element
+    LInsert(RedisKey, bool, RedisValue, RedisValue),
+    LRem(RedisKey, i64, RedisValue),
+    LTrim(RedisKey, i64, i64),
+    LPos(RedisKey, RedisValue, LPosOptions),
+    /// Source, destination and the ends to pop from and push to
+    LMove(RedisKey, RedisKey, Side, Side),
+    /// Keys, end of the lists and timeout, `None` blocks forever
+    BPop(Vec<RedisKey>, Side, Option<Duration>),
+    BLMove(RedisKey, RedisKey, Side, Side, Option<Duration>),
+    /// Keys, end of the lists and number of elements
+    LMPop(Vec<RedisKey>, Side, usize),
+    BLMPop(Vec<RedisKey>, Side, usize, Option<Duration>),
+    SAdd(RedisKey, Vec<RedisValue>),
+    SRem(RedisKey, Vec<RedisValue>),
+    SMembers(RedisKey),
+    SIsMember(RedisKey, RedisValue),
+    SMIsMember(RedisKey, Vec<RedisValue>),
+    SCard(RedisKey),
+    /// Source, destination and member
+    SMove(RedisKey, RedisKey, RedisValue),
+    /// Keys and limit, 0 for no limit
+    SInterCard(Vec<RedisKey>, usize),
+    /// Operation, destination of the STORE variants and keys
+    SCombine(set::Operation, Option<RedisKey>, Vec<RedisKey>),
+    ZAdd(RedisKey, ZAddOptions, Vec<(f64, RedisValue)>),
+    ZScore(RedisKey, RedisValue),
+    ZRem(RedisKey, Vec<RedisValue>),
+    ZCard(RedisKey),
+    ZRandMember(RedisKey, Option<i64>, bool),
+    /// Keys, MAX and count
+    ZMPop(Vec<RedisKey>, bool, usize),
+    BZMPop(Vec<RedisKey>, bool, usize, Option<Duration>),
+    ZRange(RedisKey, ZRangeOptions),
+    GeoPos(RedisKey, Vec<RedisValue>),
+    XAdd(RedisKey, XAddOptions, NewId, Vec<(RedisValue, RedisValue)>),
+    XLen(RedisKey),
+    /// Key, inclusive start and end, count and reverse
+    XRange(RedisKey, StreamId, StreamId, Option<usize>, bool),
+    /// Keys, ids (`None` for `$`), count, block and timeout
+    XRead(
+        Vec<RedisKey>,
+        Vec<Option<StreamId>>,
+        Option<usize>,
+        bool,
+        Option<Duration>,
+    ),
+    /// Key, both members and the unit in meters
+    GeoDist(RedisKey, RedisValue, RedisValue, f64),
+    /// Destination of GEOSEARCHSTORE, key and options
+    GeoSearch(Option<RedisKey>, RedisKey, GeoSearchOptions),
+    /// Destination, source and range
+    ZRangeStore(RedisKey, RedisKey, ZRangeOptions),
+    /// Operation, destination of the STORE variants, keys and options
+    ZCombine(
+        set::Operation,
+        Option<RedisKey>,
+        Vec<RedisKey>,
+        ZCombineOptions,
+    ),
+    /// ZCOUNT or ZLEXCOUNT
+    ZCount(RedisKey, ZRangeBy),
+    /// Key, member, from the highest score and WITHSCORE
+    ZRank(RedisKey, RedisValue, bool, bool),
+    Select(i64),
+    Move(RedisKey, i64),
+    SwapDb(i64, i64),
+    /// The flag is ASYNC, the contents are freed in the background
+    FlushDb(bool),
+    FlushAll(bool),
     Command,
+    #[cfg(feature = "failpoints")]
+    Failpoint(String, Option<Failpoint>),
 }
 
 impl RedisCmd {
     /// Excecute the command and return the RespValue to reply to the client
-    pub fn execute(
-        mut self,
-        storage: Arc<Mutex<HashMap<RedisKey, RedisValue>>>,
-    ) -> Result<RespValue, ()> {
+    pub fn execute(mut self, databases: &Databases, client: &mut Client) -> Result<RespValue, ()> {
+        // Database selected by the client
+        let storage = databases.select(client.db);
         let result = match &mut self {
             RedisCmd::Ping(None) => RespValue::SimpleString("PONG".into()),
             RedisCmd::Ping(Some(value)) => RespValue::BulkString(value.clone()),
             RedisCmd::Get(key) => {
                 debug!("Getting key: {}", key);
-                let storage = storage.lock().unwrap();
-                if let Some(value) = storage.get(key) {
-                    RespValue::BulkString(value.clone())
+                let mut storage = storage.lock().unwrap();
+                match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(value)) => RespValue::BulkString(value.clone()),
+                    Some(Err(err)) => RespValue::Error(err.into(), None),
+                    None => RespValue::Null,
+                }
+            }
+            RedisCmd::GetDel(key) => {
+                debug!("Getting and deleting key: {}", key);
+                let mut storage = storage.lock().unwrap();
+                match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(_)) => match storage.remove(key).map(|entry| entry.value) {
+                        Some(Value::String(value)) => RespValue::BulkString(value),
+                        _ => unreachable!(),
+                    },
+                    Some(Err(err)) => RespValue::Error(err.into(), None),
+                    None => RespValue::Null,
+                }
+            }
+            RedisCmd::GetEx(key, expiration) => {
+                debug!("Getting key: {} {:?}", key, expiration);
+                let mut storage = storage.lock().unwrap();
+                let value = match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(value)) => value.clone(),
+                    Some(Err(err)) => return Ok(RespValue::Error(err.into(), None)),
+                    None => return Ok(RespValue::Null),
+                };
+                if let Some(expiration) = expiration {
+                    let expires_at = expiration.map(|expiration| expiration.deadline(now_ms()));
+                    storage.set_expiration(key, expires_at);
+                }
+                RespValue::BulkString(value)
+            }
+            RedisCmd::Set(key, value, options) => {
+                debug!("Setting: {}: {} {:?}", key, value, options);
+                let mut storage = storage.lock().unwrap();
+                let (previous, expires_at) = match storage.get(key) {
+                    Some(entry) => match entry.value.as_string() {
+                        Ok(previous) => (Some(previous.clone()), entry.expires_at),
+                        // Any type can be overwritten, unless the previous value is requested
+                        Err(err) if options.get => return Ok(RespValue::Error(err.into(), None)),
+                        Err(_) => (None, entry.expires_at),
+                    },
+                    None => (None, None),
+                };
+                let exists = storage.contains_key(key);
+                let reply = if options.get {
+                    previous.map_or(RespValue::Null, RespValue::BulkString)
                 } else {
-                    RespValue::Null
+                    RespValue::SimpleString("OK".into())
+                };
+                match options.exists {
+                    Some(must_exist) if must_exist != exists => {
+                        return Ok(if options.get { reply } else { RespValue::Null });
+                    }
+                    _ => {}
+                }
+
+                storage.set(key.clone(), value.clone().into());
+                let expires_at = match options.expiration {
+                    Some(expiration) => Some(expiration.deadline(now_ms())),
+                    None if options.keep_ttl => expires_at,
+                    None => None,
+                };
+                if expires_at.is_some() {
+                    storage.set_expiration(key, expires_at);
                 }
+                reply
             }
-            RedisCmd::Set(key, value) => {
-                debug!("Setting: {}: {}", key, value);
-                storage.lock().unwrap().insert(key.clone(), value.clone());
+            RedisCmd::MGet(keys) => {
+                debug!("Getting keys: {:?}", keys);
+                let mut storage = storage.lock().unwrap();
+                RespValue::Array(
+                    keys.iter()
+                        // Keys holding another type are replied as missing, like redis
+                        .map(
+                            |key| match storage.get(key).map(|entry| entry.value.as_string()) {
+                                Some(Ok(value)) => RespValue::BulkString(value.clone()),
+                                _ => RespValue::Null,
+                            },
+                        )
+                        .collect(),
+                )
+            }
+            RedisCmd::MSet(pairs) => {
+                debug!("Setting: {:?}", pairs);
+                // All the keys are set under the same lock, other clients never see a partial write
+                let mut storage = storage.lock().unwrap();
+                for (key, value) in pairs.drain(..) {
+                    storage.set(key, value.into());
+                }
                 RespValue::SimpleString("OK".into())
             }
+            RedisCmd::MSetNx(pairs) => {
+                debug!("Setting if not exists: {:?}", pairs);
+                // Checked and set under the same lock
+                let mut storage = storage.lock().unwrap();
+                if pairs.iter().any(|(key, _)| storage.contains_key(key)) {
+                    return Ok(RespValue::Integer(0));
+                }
+                for (key, value) in pairs.drain(..) {
+                    storage.set(key, value.into());
+                }
+                RespValue::Integer(1)
+            }
             RedisCmd::Delete(keys) => {
                 debug!("Deleting key: {:?}", keys);
                 let mut storage = storage.lock().unwrap();
@@ -109,18 +1028,177 @@ impl RedisCmd {
                 }
                 RespValue::Integer(removed)
             }
+            RedisCmd::Unlink(keys) => {
+                debug!("Unlinking keys: {:?}", keys);
+                let removed: Vec<_> = {
+                    let mut storage = storage.lock().unwrap();
+                    keys.iter()
+                        .filter_map(|key| storage.remove(key))
+                        .map(|entry| entry.value)
+                        .collect()
+                };
+                let count = removed.len() as i64;
+                // Free the values in the blocking pool instead of the connection task
+                tokio::task::spawn_blocking(move || drop(removed));
+                RespValue::Integer(count)
+            }
             RedisCmd::Append(key, value) => {
                 debug!("Setting: {}: {}", key, value);
                 let mut storage = storage.lock().unwrap();
-                let current_value = storage
-                    .entry(key.clone())
-                    .or_insert_with(|| BulkString("".into()));
-                current_value.append(value);
-                RespValue::Integer(current_value.0.len() as i64)
+                let len = match storage
+                    .get_mut(key)
+                    .map(|entry| entry.value.as_string_mut())
+                {
+                    Some(Ok(current)) => {
+                        current.append(value);
+                        current.0.len()
+                    }
+                    Some(Err(err)) => return Ok(RespValue::Error(err.into(), None)),
+                    None => {
+                        let len = value.0.len();
+                        storage.set(key.clone(), value.clone().into());
+                        len
+                    }
+                };
+                RespValue::Integer(len as i64)
+            }
+            RedisCmd::StrLen(key) => {
+                debug!("strlen: {}", key);
+                let mut storage = storage.lock().unwrap();
+                match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(value)) => RespValue::Integer(value.0.len() as i64),
+                    Some(Err(err)) => RespValue::Error(err.into(), None),
+                    None => RespValue::Integer(0),
+                }
+            }
+            RedisCmd::GetRange(key, start, end) => {
+                debug!("getrange: {}: {} {}", key, start, end);
+                let mut storage = storage.lock().unwrap();
+                let value = match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(value)) => &value.0,
+                    Some(Err(err)) => return Ok(RespValue::Error(err.into(), None)),
+                    None => return Ok(RespValue::BulkString(BulkString(vec![]))),
+                };
+                // Negative indexes start from the end, then the range is clamped to the string
+                let len = value.len() as i64;
+                let start = if *start < 0 { len + *start } else { *start }.max(0);
+                let end = if *end < 0 { len + *end } else { *end }.min(len - 1);
+                let range = if start > end {
+                    vec![]
+                } else {
+                    value[start as usize..=end as usize].to_vec()
+                };
+                RespValue::BulkString(BulkString(range))
+            }
+            RedisCmd::SetBit(key, offset, bit) => {
+                debug!("setbit: {}: {} {}", key, offset, bit);
+                reply(bitmap::setbit(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *offset,
+                    *bit,
+                ))
+            }
+            RedisCmd::GetBit(key, offset) => {
+                debug!("getbit: {}: {}", key, offset);
+                reply(bitmap::getbit(&mut storage.lock().unwrap(), key, *offset))
+            }
+            RedisCmd::BitCount(key, range) => {
+                debug!("bitcount: {}: {:?}", key, range);
+                reply(bitmap::bitcount(&mut storage.lock().unwrap(), key, range))
+            }
+            RedisCmd::BitPos(key, bit, range) => {
+                debug!("bitpos: {}: {} {:?}", key, bit, range);
+                reply(bitmap::bitpos(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *bit,
+                    range,
+                ))
+            }
+            RedisCmd::BitOp(operation, destination, keys) => {
+                debug!("bitop: {:?} {}: {:?}", operation, destination, keys);
+                let mut storage = storage.lock().unwrap();
+                reply(bitmap::bitop(&mut storage, *operation, destination, keys))
+            }
+            RedisCmd::BitField(key, operations) => {
+                debug!("bitfield: {}: {:?}", key, operations);
+                reply(bitmap::bitfield(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    operations,
+                ))
+            }
+            RedisCmd::PfAdd(key, elements) => {
+                debug!("pfadd: {}: {:?}", key, elements);
+                reply(hyperloglog::pfadd(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    elements,
+                ))
+            }
+            RedisCmd::PfCount(keys) => {
+                debug!("pfcount: {:?}", keys);
+                reply(hyperloglog::pfcount(&mut storage.lock().unwrap(), keys))
+            }
+            RedisCmd::PfMerge(destination, keys) => {
+                debug!("pfmerge: {}: {:?}", destination, keys);
+                let mut storage = storage.lock().unwrap();
+                reply(hyperloglog::pfmerge(&mut storage, destination, keys))
+            }
+            RedisCmd::SetRange(key, offset, value) => {
+                debug!("setrange: {}: {} {}", key, offset, value);
+                if *offset < 0 {
+                    return Ok(RespValue::Error("ERR offset is out of range".into(), None));
+                }
+                let offset = *offset as usize;
+                if offset + value.0.len() > MAX_STRING_LEN {
+                    return Ok(RespValue::Error(
+                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)".into(),
+                        None,
+                    ));
+                }
+                let mut storage = storage.lock().unwrap();
+                match storage.get(key).map(|entry| entry.value.as_string()) {
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Ok(RespValue::Error(err.into(), None)),
+                    // An empty value doesn't create the key
+                    None if value.0.is_empty() => return Ok(RespValue::Integer(0)),
+                    None => storage.set(key.clone(), BulkString(vec![]).into()),
+                }
+                let current = match storage.get_mut(key).map(|entry| &mut entry.value) {
+                    Some(Value::String(current)) => current,
+                    _ => unreachable!(),
+                };
+                if !value.0.is_empty() {
+                    // Padded with zeros when the offset is past the end
+                    let end = offset + value.0.len();
+                    if current.0.len() < end {
+                        current.0.resize(end, 0);
+                    }
+                    current.0[offset..end].copy_from_slice(&value.0);
+                }
+                RespValue::Integer(current.0.len() as i64)
+            }
+            RedisCmd::IncrBy(key, increment) => {
+                debug!("incrby: {}: {}", key, increment);
+                let mut storage = storage.lock().unwrap();
+                match incr_by(&mut storage, key, *increment) {
+                    Ok(value) => RespValue::Integer(value),
+                    Err(err) => RespValue::Error(err.into(), None),
+                }
+            }
+            RedisCmd::IncrByFloat(key, increment) => {
+                debug!("incrbyfloat: {}: {}", key, increment);
+                let mut storage = storage.lock().unwrap();
+                match incr_by_float(&mut storage, key, *increment) {
+                    Ok(value) => RespValue::BulkString(value),
+                    Err(err) => RespValue::Error(err.into(), None),
+                }
             }
             RedisCmd::Keys(pattern) => {
                 debug!("pattern: {}", pattern);
-                let storage = storage.lock().unwrap();
+                let mut storage = storage.lock().unwrap();
                 RespValue::Array(
                     storage
                         .keys()
@@ -130,28 +1208,872 @@ impl RedisCmd {
             }
             RedisCmd::Exists(key) => {
                 debug!("exists: {}", key);
-                let storage = storage.lock().unwrap();
+                let mut storage = storage.lock().unwrap();
                 RespValue::Integer(storage.contains_key(key).into())
             }
-            RedisCmd::FlushAll => {
-                debug!("flush all");
+            RedisCmd::Dump(key) => {
+                debug!("dump: {}", key);
+                let mut storage = storage.lock().unwrap();
+                match storage.peek(key).map(|entry| rdb::dump(&entry.value)) {
+                    Some(Ok(payload)) => RespValue::BulkString(BulkString(payload)),
+                    Some(Err(err)) => RespValue::Error(err.into(), None),
+                    None => RespValue::Null,
+                }
+            }
+            RedisCmd::Restore(key, ttl, payload, options) => {
+                debug!("restore: {}: {} {:?}", key, ttl, options);
+                let value = match rdb::restore(&payload.0) {
+                    Ok(value) => value,
+                    Err(err) => return Ok(RespValue::Error(err.into(), None)),
+                };
                 let mut storage = storage.lock().unwrap();
-                storage.clear();
+                if !options.replace && storage.contains_key(key) {
+                    return Ok(RespValue::Error(
+                        "BUSYKEY Target key name already exists.".into(),
+                        None,
+                    ));
+                }
+                let now = now_ms();
+                let expires_at = match *ttl {
+                    0 => None,
+                    ttl if options.abs_ttl => Some(ttl),
+                    ttl => Some(now.saturating_add(ttl)),
+                };
+                storage.set(key.clone(), value);
+                // An expiration in the past removes the key right away
+                if expires_at.is_some() && !storage.set_expiration(key, expires_at) {
+                    return Ok(RespValue::SimpleString("OK".into()));
+                }
+                if let (Some(idle_time), Some(entry)) = (options.idle_time, storage.get_mut(key)) {
+                    entry.accessed_at = now - idle_time.saturating_mul(1000);
+                }
                 RespValue::SimpleString("OK".into())
             }
-            // Unimplemented command
-            cmd => {
-                debug!("Unimplemented command: {:?}", cmd);
-                return Err(());
+            RedisCmd::Object(subcommand, key) => {
+                debug!("object: {:?}: {}", subcommand, key);
+                let mut storage = storage.lock().unwrap();
+                // Inspecting a key doesn't change its access time
+                let entry = match storage.peek(key) {
+                    Some(entry) => entry,
+                    None => return Ok(RespValue::Null),
+                };
+                match subcommand {
+                    ObjectSubcommand::Encoding => {
+                        RespValue::BulkString(BulkString(entry.value.encoding().into()))
+                    }
+                    // Values are never shared between keys
+                    ObjectSubcommand::RefCount => RespValue::Integer(1),
+                    ObjectSubcommand::IdleTime => {
+                        RespValue::Integer((now_ms() - entry.accessed_at).max(0) / 1000)
+                    }
+                    ObjectSubcommand::Freq => RespValue::Error(
+                        "ERR An LFU maxmemory policy is not selected, access frequency not tracked."
+                            .into(),
+                        None,
+                    ),
+                }
             }
-        };
-
-        Ok(result)
-    }
-}
-
-/// Get the next argument from a RespValue::Array
-fn get_next_value(resp: &mut VecDeque<RespValue>) -> Result<BulkString, &'static str> {
+            RedisCmd::Touch(keys) => {
+                debug!("touch: {:?}", keys);
+                let mut storage = storage.lock().unwrap();
+                let touched = keys.iter().filter(|key| storage.get(key).is_some()).count();
+                RespValue::Integer(touched as i64)
+            }
+            RedisCmd::RandomKey => {
+                debug!("randomkey");
+                let mut storage = storage.lock().unwrap();
+                storage
+                    .random_key()
+                    .map_or(RespValue::Null, RespValue::BulkString)
+            }
+            RedisCmd::DbSize => {
+                debug!("dbsize");
+                let mut storage = storage.lock().unwrap();
+                RespValue::Integer(storage.count() as i64)
+            }
+            RedisCmd::Expire(key, expiration, options) => {
+                debug!("expire: {}: {:?} {:?}", key, expiration, options);
+                let expires_at = expiration.deadline(now_ms());
+                let mut storage = storage.lock().unwrap();
+                let current = match storage.peek(key) {
+                    Some(entry) => entry.expires_at,
+                    None => return Ok(RespValue::Integer(0)),
+                };
+                if !options.allows(current, expires_at) {
+                    return Ok(RespValue::Integer(0));
+                }
+                RespValue::Integer(storage.set_expiration(key, Some(expires_at)).into())
+            }
+            RedisCmd::Ttl(key)
+            | RedisCmd::PTtl(key)
+            | RedisCmd::ExpireTime(key)
+            | RedisCmd::PExpireTime(key) => {
+                debug!("ttl: {}", key);
+                let mut storage = storage.lock().unwrap();
+                let expires_at = match storage.peek(key) {
+                    None => return Ok(RespValue::Integer(-2)),
+                    Some(entry) => match entry.expires_at {
+                        None => return Ok(RespValue::Integer(-1)),
+                        Some(expires_at) => expires_at,
+                    },
+                };
+                let ttl = match self {
+                    RedisCmd::ExpireTime(_) | RedisCmd::PExpireTime(_) => expires_at,
+                    _ => (expires_at - now_ms()).max(0),
+                };
+                match self {
+                    // Rounded to the closest second, like redis
+                    RedisCmd::Ttl(_) | RedisCmd::ExpireTime(_) => {
+                        RespValue::Integer((ttl + 500) / 1000)
+                    }
+                    _ => RespValue::Integer(ttl),
+                }
+            }
+            RedisCmd::Persist(key) => {
+                debug!("persist: {}", key);
+                let mut storage = storage.lock().unwrap();
+                let persisted = match storage.peek(key) {
+                    Some(entry) => entry.expires_at.is_some(),
+                    None => false,
+                };
+                if persisted {
+                    storage.set_expiration(key, None);
+                }
+                RespValue::Integer(persisted.into())
+            }
+            RedisCmd::HSet(key, pairs) => {
+                debug!("hset: {}: {:?}", key, pairs);
+                let mut storage = storage.lock().unwrap();
+                reply(hash::hset(&mut storage, key, std::mem::take(pairs)))
+            }
+            RedisCmd::HSetNx(key, field, value) => {
+                debug!("hsetnx: {}: {} {}", key, field, value);
+                reply(hash::hsetnx(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    field,
+                    value,
+                ))
+            }
+            RedisCmd::HStrLen(key, field) => {
+                debug!("hstrlen: {}: {}", key, field);
+                reply(hash::hstrlen(&mut storage.lock().unwrap(), key, field))
+            }
+            RedisCmd::HGet(key, field) => {
+                debug!("hget: {}: {}", key, field);
+                reply(hash::hget(&mut storage.lock().unwrap(), key, field))
+            }
+            RedisCmd::HMSet(key, pairs) => {
+                debug!("hmset: {}: {:?}", key, pairs);
+                let mut storage = storage.lock().unwrap();
+                let result = hash::hset(&mut storage, key, std::mem::take(pairs));
+                reply(result.map(|_| RespValue::SimpleString("OK".into())))
+            }
+            RedisCmd::HMGet(key, fields) => {
+                debug!("hmget: {}: {:?}", key, fields);
+                reply(hash::hmget(&mut storage.lock().unwrap(), key, fields))
+            }
+            RedisCmd::HDel(key, fields) => {
+                debug!("hdel: {}: {:?}", key, fields);
+                reply(hash::hdel(&mut storage.lock().unwrap(), key, fields))
+            }
+            RedisCmd::HGetAll(key) => {
+                debug!("hgetall: {}", key);
+                reply(hash::hgetall(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::HKeys(key) => {
+                debug!("hkeys: {}", key);
+                reply(hash::hkeys(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::HVals(key) => {
+                debug!("hvals: {}", key);
+                reply(hash::hvals(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::HLen(key) => {
+                debug!("hlen: {}", key);
+                reply(hash::hlen(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::HExists(key, field) => {
+                debug!("hexists: {}: {}", key, field);
+                reply(hash::hexists(&mut storage.lock().unwrap(), key, field))
+            }
+            RedisCmd::HScan(key, cursor, options) => {
+                debug!("hscan: {}: {} {:?}", key, cursor, options);
+                reply(hash::hscan(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *cursor,
+                    options,
+                ))
+            }
+            RedisCmd::HRandField(key, count, with_values) => {
+                debug!("hrandfield: {}: {:?} {}", key, count, with_values);
+                let mut storage = storage.lock().unwrap();
+                reply(hash::hrandfield(&mut storage, key, *count, *with_values))
+            }
+            RedisCmd::HExpire(key, expiration, options, fields) => {
+                debug!(
+                    "hexpire: {}: {:?} {:?} {:?}",
+                    key, expiration, options, fields
+                );
+                let expires_at = expiration.deadline(now_ms());
+                let mut storage = storage.lock().unwrap();
+                reply(hash::hexpire(
+                    &mut storage,
+                    key,
+                    expires_at,
+                    options,
+                    fields,
+                ))
+            }
+            RedisCmd::HTtl(key, fields) => {
+                debug!("httl: {}: {:?}", key, fields);
+                reply(hash::httl(&mut storage.lock().unwrap(), key, fields, false))
+            }
+            RedisCmd::HPTtl(key, fields) => {
+                debug!("hpttl: {}: {:?}", key, fields);
+                reply(hash::httl(&mut storage.lock().unwrap(), key, fields, true))
+            }
+            RedisCmd::HPersist(key, fields) => {
+                debug!("hpersist: {}: {:?}", key, fields);
+                reply(hash::hpersist(&mut storage.lock().unwrap(), key, fields))
+            }
+            RedisCmd::Push(key, side, existing, elements) => {
+                debug!("push: {}: {:?} {} {:?}", key, side, existing, elements);
+                let mut storage = storage.lock().unwrap();
+                let elements = std::mem::take(elements);
+                reply(list::push(&mut storage, key, *side, *existing, elements))
+            }
+            RedisCmd::Pop(key, side, count) => {
+                debug!("pop: {}: {:?} {:?}", key, side, count);
+                reply(list::pop(&mut storage.lock().unwrap(), key, *side, *count))
+            }
+            RedisCmd::LLen(key) => {
+                debug!("llen: {}", key);
+                reply(list::llen(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::LRange(key, start, end) => {
+                debug!("lrange: {}: {} {}", key, start, end);
+                reply(list::lrange(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *start,
+                    *end,
+                ))
+            }
+            RedisCmd::LIndex(key, index) => {
+                debug!("lindex: {}: {}", key, index);
+                reply(list::lindex(&mut storage.lock().unwrap(), key, *index))
+            }
+            RedisCmd::LSet(key, index, element) => {
+                debug!("lset: {}: {} {}", key, index, element);
+                reply(list::lset(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *index,
+                    element,
+                ))
+            }
+            RedisCmd::LInsert(key, before, pivot, element) => {
+                debug!("linsert: {}: {} {} {}", key, before, pivot, element);
+                let mut storage = storage.lock().unwrap();
+                reply(list::linsert(&mut storage, key, *before, pivot, element))
+            }
+            RedisCmd::LRem(key, count, element) => {
+                debug!("lrem: {}: {} {}", key, count, element);
+                reply(list::lrem(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    *count,
+                    element,
+                ))
+            }
+            RedisCmd::LTrim(key, start, end) => {
+                debug!("ltrim: {}: {} {}", key, start, end);
+                reply(list::ltrim(&mut storage.lock().unwrap(), key, *start, *end))
+            }
+            RedisCmd::LPos(key, element, options) => {
+                debug!("lpos: {}: {} {:?}", key, element, options);
+                reply(list::lpos(
+                    &mut storage.lock().unwrap(),
+                    key,
+                    element,
+                    options,
+                ))
+            }
+            RedisCmd::LMove(source, destination, from, to) => {
+                debug!("lmove: {} {}: {:?} {:?}", source, destination, from, to);
+                let mut storage = storage.lock().unwrap();
+                reply(list::lmove(&mut storage, source, destination, *from, *to))
+            }
+            RedisCmd::BPop(keys, side, timeout) => {
+                debug!("bpop: {:?}: {:?} {:?}", keys, side, timeout);
+                let mut storage = storage.lock().unwrap();
+                match list::bpop(&mut storage, keys, *side) {
+                    Ok(RespValue::Null) => {
+                        let command = RedisCmd::BPop(keys.clone(), *side, *timeout);
+                        client.block(&mut storage, command, keys, *timeout);
+                        RespValue::NullArray
+                    }
+                    result => reply(result),
+                }
+            }
+            RedisCmd::BLMove(source, destination, from, to, timeout) => {
+                debug!(
+                    "blmove: {} {}: {:?} {:?} {:?}",
+                    source, destination, from, to, timeout
+                );
+                let mut storage = storage.lock().unwrap();
+                match list::lmove(&mut storage, source, destination, *from, *to) {
+                    Ok(RespValue::Null) => {
+                        let command = RedisCmd::BLMove(
+                            source.clone(),
+                            destination.clone(),
+                            *from,
+                            *to,
+                            *timeout,
+                        );
+                        let keys = [source.clone()];
+                        client.block(&mut storage, command, &keys, *timeout);
+                        RespValue::NullArray
+                    }
+                    result => reply(result),
+                }
+            }
+            RedisCmd::LMPop(keys, side, count) => {
+                debug!("lmpop: {:?}: {:?} {}", keys, side, count);
+                reply(list::lmpop(
+                    &mut storage.lock().unwrap(),
+                    keys,
+                    *side,
+                    *count,
+                ))
+            }
+            RedisCmd::BLMPop(keys, side, count, timeout) => {
+                debug!("blmpop: {:?}: {:?} {} {:?}", keys, side, count, timeout);
+                let mut storage = storage.lock().unwrap();
+                match list::lmpop(&mut storage, keys, *side, *count) {
+                    Ok(RespValue::NullArray) => {
+                        let command = RedisCmd::BLMPop(keys.clone(), *side, *count, *timeout);
+                        client.block(&mut storage, command, keys, *timeout);
+                        RespValue::NullArray
+                    }
+                    result => reply(result),
+                }
+            }
+            RedisCmd::SAdd(key, members) => {
+                debug!("sadd: {}: {:?}", key, members);
+                let mut storage = storage.lock().unwrap();
+                reply(set::sadd(&mut storage, key, std::mem::take(members)))
+            }
+            RedisCmd::SRem(key, members) => {
+                debug!("srem: {}: {:?}", key, members);
+                reply(set::srem(&mut storage.lock().unwrap(), key, members))
+            }
+            RedisCmd::SMembers(key) => {
+                debug!("smembers: {}", key);
+                reply(set::smembers(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::SIsMember(key, member) => {
+                debug!("sismember: {}: {}", key, member);
+                reply(set::sismember(&mut storage.lock().unwrap(), key, member))
+            }
+            RedisCmd::SMIsMember(key, members) => {
+                debug!("smismember: {}: {:?}", key, members);
+                reply(set::smismember(&mut storage.lock().unwrap(), key, members))
+            }
+            RedisCmd::SCard(key) => {
+                debug!("scard: {}", key);
+                reply(set::scard(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::SMove(source, destination, member) => {
+                debug!("smove: {} {}: {}", source, destination, member);
+                let mut storage = storage.lock().unwrap();
+                reply(set::smove(&mut storage, source, destination, member))
+            }
+            RedisCmd::SInterCard(keys, limit) => {
+                debug!("sintercard: {:?}: {}", keys, limit);
+                reply(set::sintercard(&mut storage.lock().unwrap(), keys, *limit))
+            }
+            RedisCmd::SCombine(operation, destination, keys) => {
+                debug!("scombine: {:?} {:?}: {:?}", operation, destination, keys);
+                let mut storage = storage.lock().unwrap();
+                reply(match destination {
+                    Some(destination) => {
+                        set::combine_store(&mut storage, *operation, destination, keys)
+                    }
+                    None => set::combine(&mut storage, *operation, keys),
+                })
+            }
+            RedisCmd::ZAdd(key, options, members) => {
+                debug!("zadd: {}: {:?} {:?}", key, options, members);
+                let mut storage = storage.lock().unwrap();
+                let members = std::mem::take(members);
+                reply(zset::zadd(&mut storage, key, options, members))
+            }
+            RedisCmd::ZScore(key, member) => {
+                debug!("zscore: {}: {}", key, member);
+                reply(zset::zscore(&mut storage.lock().unwrap(), key, member))
+            }
+            RedisCmd::ZRem(key, members) => {
+                debug!("zrem: {}: {:?}", key, members);
+                reply(zset::zrem(&mut storage.lock().unwrap(), key, members))
+            }
+            RedisCmd::ZCard(key) => {
+                debug!("zcard: {}", key);
+                reply(zset::zcard(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::ZRandMember(key, count, with_scores) => {
+                debug!("zrandmember: {}: {:?} {}", key, count, with_scores);
+                let mut storage = storage.lock().unwrap();
+                reply(zset::zrandmember(&mut storage, key, *count, *with_scores))
+            }
+            RedisCmd::ZMPop(keys, max, count) => {
+                debug!("zmpop: {:?}: {} {}", keys, max, count);
+                reply(zset::zmpop(
+                    &mut storage.lock().unwrap(),
+                    keys,
+                    *max,
+                    *count,
+                ))
+            }
+            RedisCmd::BZMPop(keys, max, count, timeout) => {
+                debug!("bzmpop: {:?}: {} {} {:?}", keys, max, count, timeout);
+                let mut storage = storage.lock().unwrap();
+                match zset::zmpop(&mut storage, keys, *max, *count) {
+                    Ok(RespValue::NullArray) => {
+                        let command = RedisCmd::BZMPop(keys.clone(), *max, *count, *timeout);
+                        client.block(&mut storage, command, keys, *timeout);
+                        RespValue::NullArray
+                    }
+                    result => reply(result),
+                }
+            }
+            RedisCmd::XAdd(key, options, id, fields) => {
+                debug!("xadd: {}: {:?} {:?} {:?}", key, options, id, fields);
+                let mut storage = storage.lock().unwrap();
+                let fields = std::mem::take(fields);
+                reply(stream::xadd(&mut storage, key, options, *id, fields))
+            }
+            RedisCmd::XLen(key) => {
+                debug!("xlen: {}", key);
+                reply(stream::xlen(&mut storage.lock().unwrap(), key))
+            }
+            RedisCmd::XRange(key, start, end, count, rev) => {
+                debug!("xrange: {}: {:?} {:?} {:?} {}", key, start, end, count, rev);
+                let mut storage = storage.lock().unwrap();
+                reply(stream::xrange(
+                    &mut storage,
+                    key,
+                    *start,
+                    *end,
+                    *count,
+                    *rev,
+                ))
+            }
+            RedisCmd::XRead(keys, ids, count, block, timeout) => {
+                debug!(
+                    "xread: {:?}: {:?} {:?} {} {:?}",
+                    keys, ids, count, block, timeout
+                );
+                let mut storage = storage.lock().unwrap();
+                // Retries wait for entries after the ones that were the last of the first attempt
+                let ids = match stream::resolve_ids(&mut storage, keys, ids) {
+                    Ok(ids) => ids,
+                    Err(err) => return Ok(RespValue::Error(err.into(), None)),
+                };
+                match stream::xread(&mut storage, keys, &ids, *count) {
+                    Ok(RespValue::NullArray) if *block => {
+                        let ids = ids.into_iter().map(Some).collect();
+                        let command = RedisCmd::XRead(keys.clone(), ids, *count, true, *timeout);
+                        client.block(&mut storage, command, keys, *timeout);
+                        RespValue::NullArray
+                    }
+                    result => reply(result),
+                }
+            }
+            RedisCmd::GeoPos(key, members) => {
+                debug!("geopos: {}: {:?}", key, members);
+                reply(geo::geopos(&mut storage.lock().unwrap(), key, members))
+            }
+            RedisCmd::GeoDist(key, first, second, unit) => {
+                debug!("geodist: {}: {} {} {}", key, first, second, unit);
+                let mut storage = storage.lock().unwrap();
+                reply(geo::geodist(&mut storage, key, first, second, *unit))
+            }
+            RedisCmd::GeoSearch(destination, key, options) => {
+                debug!("geosearch: {:?} {}: {:?}", destination, key, options);
+                let mut storage = storage.lock().unwrap();
+                match destination {
+                    Some(destination) => {
+                        reply(geo::geosearchstore(&mut storage, destination, key, options))
+                    }
+                    None => reply(geo::geosearch(&mut storage, key, options)),
+                }
+            }
+            RedisCmd::ZRange(key, options) => {
+                debug!("zrange: {}: {:?}", key, options);
+                reply(zset::zrange(&mut storage.lock().unwrap(), key, options))
+            }
+            RedisCmd::ZRangeStore(destination, source, options) => {
+                debug!("zrangestore: {} {}: {:?}", destination, source, options);
+                let mut storage = storage.lock().unwrap();
+                reply(zset::zrangestore(
+                    &mut storage,
+                    destination,
+                    source,
+                    options,
+                ))
+            }
+            RedisCmd::ZCombine(operation, destination, keys, options) => {
+                debug!(
+                    "zcombine: {:?} {:?}: {:?} {:?}",
+                    operation, destination, keys, options
+                );
+                let mut storage = storage.lock().unwrap();
+                reply(match destination {
+                    Some(destination) => {
+                        zset::combine_store(&mut storage, *operation, destination, keys, options)
+                    }
+                    None => zset::combine(&mut storage, *operation, keys, options),
+                })
+            }
+            RedisCmd::ZCount(key, by) => {
+                debug!("zcount: {}: {:?}", key, by);
+                reply(zset::zcount(&mut storage.lock().unwrap(), key, by))
+            }
+            RedisCmd::ZRank(key, member, rev, with_score) => {
+                debug!("zrank: {}: {} {} {}", key, member, rev, with_score);
+                let mut storage = storage.lock().unwrap();
+                reply(zset::zrank(&mut storage, key, member, *rev, *with_score))
+            }
+            RedisCmd::Select(index) => {
+                debug!("select: {}", index);
+                if *index < 0 || *index as usize >= databases.len() {
+                    return Ok(RespValue::Error(
+                        "ERR DB index is out of range".into(),
+                        None,
+                    ));
+                }
+                client.db = *index as usize;
+                RespValue::SimpleString("OK".into())
+            }
+            RedisCmd::Move(key, index) => {
+                debug!("move: {}: {}", key, index);
+                if *index < 0 || *index as usize >= databases.len() {
+                    return Ok(RespValue::Error(
+                        "ERR DB index is out of range".into(),
+                        None,
+                    ));
+                }
+                let index = *index as usize;
+                if index == client.db {
+                    return Ok(RespValue::Error(
+                        "ERR source and destination objects are the same".into(),
+                        None,
+                    ));
+                }
+                let (mut source, mut destination) = databases.lock_pair(client.db, index);
+                if destination.contains_key(key) {
+                    return Ok(RespValue::Integer(0));
+                }
+                match source.remove(key) {
+                    Some(entry) => {
+                        destination.insert(key.clone(), entry);
+                        RespValue::Integer(1)
+                    }
+                    None => RespValue::Integer(0),
+                }
+            }
+            RedisCmd::SwapDb(first, second) => {
+                debug!("swapdb: {} {}", first, second);
+                let valid = |index: i64| index >= 0 && (index as usize) < databases.len();
+                if !valid(*first) || !valid(*second) {
+                    return Ok(RespValue::Error(
+                        "ERR DB index is out of range".into(),
+                        None,
+                    ));
+                }
+                if first != second {
+                    // Both locks are held, clients see either the old or the new contents
+                    let (mut first, mut second) =
+                        databases.lock_pair(*first as usize, *second as usize);
+                    first.swap(&mut second);
+                }
+                RespValue::SimpleString("OK".into())
+            }
+            RedisCmd::FlushDb(asynchronous) => {
+                debug!("flush db: {}", client.db);
+                flush(storage, *asynchronous);
+                RespValue::SimpleString("OK".into())
+            }
+            RedisCmd::FlushAll(asynchronous) => {
+                debug!("flush all");
+                for storage in databases.iter() {
+                    flush(storage, *asynchronous);
+                }
+                RespValue::SimpleString("OK".into())
+            }
+            #[cfg(feature = "failpoints")]
+            RedisCmd::Failpoint(command, failpoint) => {
+                debug!("failpoint: {}: {:?}", command, failpoint);
+                failpoints::set(command, failpoint.take());
+                RespValue::SimpleString("OK".into())
+            }
+            // Unimplemented command
+            cmd => {
+                debug!("Unimplemented command: {:?}", cmd);
+                return Err(());
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+/// Add to the integer stored in a key, the key is created at 0 when missing and keeps its TTL
+fn incr_by(storage: &mut Storage, key: &RedisKey, increment: i64) -> Result<i64, &'static str> {
+    let current = match storage.get(key) {
+        Some(entry) => entry
+            .value
+            .as_string()?
+            .to_string()
+            .parse::<i64>()
+            .map_err(|_| NOT_INTEGER)?,
+        None => 0,
+    };
+    let value = current.checked_add(increment).ok_or(NOT_INTEGER)?;
+    storage.replace(
+        key,
+        Value::String(BulkString(value.to_string().into_bytes())),
+    );
+    Ok(value)
+}
+
+/// Add to the float stored in a key, like `incr_by`
+/// The result is formatted by `format_float`, ie. `10.5` or `3`
+fn incr_by_float(
+    storage: &mut Storage,
+    key: &RedisKey,
+    increment: f64,
+) -> Result<BulkString, &'static str> {
+    let current = match storage.get(key) {
+        Some(entry) => parse_float(&entry.value.as_string()?.to_string())?,
+        None => 0.0,
+    };
+    let value = current + increment;
+    if !value.is_finite() {
+        return Err("ERR increment would produce NaN or Infinity");
+    }
+    let value = BulkString(format_float(value).into_bytes());
+    storage.replace(key, Value::String(value.clone()));
+    Ok(value)
+}
+
+/// Format a float like redis with `%.17Lf`, without exponent nor trailing zeros
+/// Redis adds long doubles, with f64 the digits past the 16th significant one are noise of the
+/// addition so they are rounded, ie. `10.1 + 0.2` is `10.3`
+fn format_float(value: f64) -> String {
+    const DIGITS: i32 = 16;
+    const DECIMALS: i32 = 17;
+    // Exponent after rounding to the significant digits
+    let scientific = format!("{:.*e}", DIGITS as usize - 1, value);
+    let exponent: i32 = scientific[scientific.find('e').unwrap() + 1..]
+        .parse()
+        .unwrap();
+    let decimals = (DIGITS - 1 - exponent).clamp(0, DECIMALS);
+    let fixed = format!("{:.*}", decimals as usize, value);
+    if decimals == 0 {
+        return fixed;
+    }
+    fixed
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Parse a float like redis, `inf` and `nan` are rejected
+fn parse_float(value: &str) -> Result<f64, &'static str> {
+    match value.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(NOT_FLOAT),
+    }
+}
+
+/// Parse the score of a member of a sorted set, unlike other floats `inf` and `-inf` are valid
+fn parse_score(value: &BulkString) -> Result<f64, &'static str> {
+    match value.to_string().parse::<f64>() {
+        Ok(score) if !score.is_nan() => Ok(score),
+        _ => Err("ERR value is not a valid float"),
+    }
+}
+
+/// Parse a bound of a range of scores, exclusive when prefixed by `(`
+fn parse_score_bound(value: &BulkString) -> Result<Bound<f64>, &'static str> {
+    let (value, exclusive) = match value.0.split_first() {
+        Some((b'(', rest)) => (BulkString(rest.to_vec()), true),
+        _ => (value.clone(), false),
+    };
+    let score = parse_score(&value).map_err(|_| "ERR min or max is not a float")?;
+    if exclusive {
+        Ok(Bound::Excluded(score))
+    } else {
+        Ok(Bound::Included(score))
+    }
+}
+
+/// Parse a bound of a range of members, `[` for inclusive and `(` for exclusive ones, or `-`
+/// and `+` for no bound
+fn parse_lex_bound(value: BulkString) -> Result<Bound<BulkString>, &'static str> {
+    match value.0.split_first() {
+        Some((b'-', [])) | Some((b'+', [])) => Ok(Bound::Unbounded),
+        Some((b'[', rest)) => Ok(Bound::Included(BulkString(rest.to_vec()))),
+        Some((b'(', rest)) => Ok(Bound::Excluded(BulkString(rest.to_vec()))),
+        _ => Err("ERR min or max not valid string range item"),
+    }
+}
+
+/// Reply of a command implemented in the module of its type, errors are sent to the client
+fn reply(result: Result<RespValue, &'static str>) -> RespValue {
+    result.unwrap_or_else(|err| RespValue::Error(err.into(), None))
+}
+
+/// Remove all the keys of a database
+/// When asynchronous the old keys are taken out under the lock and freed in the blocking pool,
+/// so dropping millions of entries doesn't stall the connection tasks
+fn flush(storage: &Mutex<Storage>, asynchronous: bool) {
+    let mut storage = storage.lock().unwrap();
+    if asynchronous {
+        let old = storage.take();
+        tokio::task::spawn_blocking(move || drop(old));
+    } else {
+        storage.clear();
+    }
+}
+
+/// Get the optional ASYNC or SYNC flag of FLUSHDB and FLUSHALL, true for ASYNC
+fn get_flush_mode(resp: &mut VecDeque<RespValue>) -> Result<bool, &'static str> {
+    let mode = match resp.pop_front() {
+        None => return Ok(false),
+        Some(RespValue::BulkString(mode)) => mode.to_string().to_uppercase(),
+        Some(_) => return Err("ERR syntax error"),
+    };
+    match mode.as_ref() {
+        "ASYNC" if resp.is_empty() => Ok(true),
+        "SYNC" if resp.is_empty() => Ok(false),
+        _ => Err("ERR syntax error"),
+    }
+}
+
+/// Get the next argument as the end of a list, LEFT or RIGHT
+fn get_side(resp: &mut VecDeque<RespValue>) -> Result<Side, &'static str> {
+    match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+        "LEFT" => Ok(Side::Left),
+        "RIGHT" => Ok(Side::Right),
+        _ => Err("ERR syntax error"),
+    }
+}
+
+/// Get the last argument as the timeout of a blocking command
+fn get_timeout(resp: &mut VecDeque<RespValue>) -> Result<Option<Duration>, &'static str> {
+    match resp.pop_back() {
+        Some(RespValue::BulkString(timeout)) => parse_timeout(&timeout),
+        _ => Err("Not enough arguments"),
+    }
+}
+
+/// Parse a timeout in seconds of a blocking command, `None` for 0
+fn parse_timeout(timeout: &BulkString) -> Result<Option<Duration>, &'static str> {
+    let timeout = parse_float(&timeout.to_string())
+        .map_err(|_| "ERR timeout is not a float or out of range")?;
+    timeout_from_millis(timeout * 1000.0)
+}
+
+/// Timeout of a blocking command in milliseconds, `None` for 0
+/// Like redis, the deadline must fit in an i64 of unix time in milliseconds
+fn timeout_from_millis(millis: f64) -> Result<Option<Duration>, &'static str> {
+    if millis < 0.0 {
+        return Err("ERR timeout is negative");
+    }
+    if millis > (i64::MAX - now_ms()) as f64 {
+        return Err("ERR timeout is out of range");
+    }
+    if millis == 0.0 {
+        return Ok(None);
+    }
+    Ok(Some(Duration::from_secs_f64(millis / 1000.0)))
+}
+
+/// Get the end of a sorted set of ZMPOP, true for MAX
+fn get_min_max(resp: &mut VecDeque<RespValue>) -> Result<bool, &'static str> {
+    match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+        "MIN" => Ok(false),
+        "MAX" => Ok(true),
+        _ => Err("ERR syntax error"),
+    }
+}
+
+/// Get the `[count [<option>]]` arguments of HRANDFIELD and ZRANDMEMBER, the option replies the
+/// values too. Positive counts are limited like in redis so the length of the reply fits an i64,
+/// negative ones to `random::MAX_REPEATED`
+fn get_random_count(
+    resp: &mut VecDeque<RespValue>,
+    option: &str,
+) -> Result<(Option<i64>, bool), &'static str> {
+    if resp.is_empty() {
+        return Ok((None, false));
+    }
+    let count = get_next_integer(resp)?;
+    if !(-(random::MAX_REPEATED as i64)..=i64::MAX / 2).contains(&count) {
+        return Err("ERR value is out of range");
+    }
+    let with_values = match get_next_value(resp) {
+        Ok(value) if value.to_string().to_uppercase() == option => true,
+        Ok(_) => return Err("ERR syntax error"),
+        Err(_) => false,
+    };
+    if !resp.is_empty() {
+        return Err("ERR syntax error");
+    }
+    Ok((Some(count), with_values))
+}
+
+/// Get the `numkeys key [key ...] <end> [COUNT count]` arguments of LMPOP and ZMPOP, the end is
+/// parsed by `get_end`
+fn get_mpop<T>(
+    resp: &mut VecDeque<RespValue>,
+    get_end: fn(&mut VecDeque<RespValue>) -> Result<T, &'static str>,
+) -> Result<(Vec<RedisKey>, T, usize), &'static str> {
+    let count = get_next_integer(resp)?;
+    if count <= 0 {
+        return Err("ERR numkeys should be greater than 0");
+    }
+    if count as usize >= resp.len() {
+        return Err("ERR syntax error");
+    }
+    let keys = resp
+        .drain(..count as usize)
+        .map(|key| match key {
+            RespValue::BulkString(key) => Ok(key),
+            _ => Err("Invalid argument, must be BulkString"),
+        })
+        .collect::<Result<_, _>>()?;
+    let side = get_end(resp)?;
+    let count = match resp.len() {
+        0 => 1,
+        2 if get_next_value(resp)?.to_string().to_uppercase() == "COUNT" => {
+            match get_next_integer(resp)? {
+                count if count > 0 => count as usize,
+                _ => return Err("ERR count should be greater than 0"),
+            }
+        }
+        _ => return Err("ERR syntax error"),
+    };
+    Ok((keys, side, count))
+}
+
+/// Get the next argument from a RespValue::Array
+fn get_next_value(resp: &mut VecDeque<RespValue>) -> Result<BulkString, &'static str> {
     match resp.pop_front().ok_or("Not enough arguments") {
         Ok(value) => match value {
             RespValue::BulkString(value) => Ok(value),
@@ -161,6 +2083,193 @@ fn get_next_value(resp: &mut VecDeque<RespValue>) -> Result<BulkString, &'static
     }
 }
 
+/// Get the next argument from a RespValue::Array as an integer
+fn get_next_integer(resp: &mut VecDeque<RespValue>) -> Result<i64, &'static str> {
+    get_next_value(resp)?
+        .to_string()
+        .parse()
+        .map_err(|_| NOT_INTEGER)
+}
+
+/// Get the next argument as the offset of a bit, it has to be inside the maximum string length
+fn get_bit_offset(resp: &mut VecDeque<RespValue>) -> Result<usize, &'static str> {
+    match get_next_value(resp)?.to_string().parse::<usize>() {
+        Ok(offset) if offset / 8 < MAX_STRING_LEN => Ok(offset),
+        _ => Err("ERR bit offset is not an integer or out of range"),
+    }
+}
+
+/// Get the subcommands of BITFIELD, the integer types are like `i16` or `u8` and offsets can be
+/// multiplied by the width of the type with a `#` prefix
+fn get_bitfield_ops(
+    resp: &mut VecDeque<RespValue>,
+) -> Result<Vec<bitmap::BitFieldOp>, &'static str> {
+    let get_type = |resp: &mut VecDeque<RespValue>| {
+        let kind = get_next_value(resp)?.to_string();
+        let signed = match kind.get(..1) {
+            Some("i") | Some("I") => true,
+            Some("u") | Some("U") => false,
+            _ => return Err(BITFIELD_TYPE),
+        };
+        match kind[1..].parse() {
+            Ok(bits) if bits >= 1 && (bits < 64 || signed && bits == 64) => {
+                Ok(bitmap::BitFieldType { signed, bits })
+            }
+            _ => Err(BITFIELD_TYPE),
+        }
+    };
+    let get_offset = |resp: &mut VecDeque<RespValue>, kind: bitmap::BitFieldType| {
+        let offset = get_next_value(resp)?.to_string();
+        let offset = match offset.strip_prefix('#') {
+            Some(index) => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| index.checked_mul(kind.bits as usize)),
+            None => offset.parse::<usize>().ok(),
+        };
+        // The last bit of the integer must fit in the longest string
+        let last = offset.and_then(|offset| offset.checked_add(kind.bits as usize - 1));
+        match (offset, last) {
+            (Some(offset), Some(last)) if last / 8 < MAX_STRING_LEN => Ok(offset),
+            _ => Err("ERR bit offset is not an integer or out of range"),
+        }
+    };
+
+    let mut operations = Vec::new();
+    while !resp.is_empty() {
+        let operation = match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+            "GET" => {
+                let kind = get_type(resp)?;
+                bitmap::BitFieldOp::Get(kind, get_offset(resp, kind)?)
+            }
+            "SET" => {
+                let kind = get_type(resp)?;
+                let offset = get_offset(resp, kind)?;
+                bitmap::BitFieldOp::Set(kind, offset, get_next_integer(resp)?)
+            }
+            "INCRBY" => {
+                let kind = get_type(resp)?;
+                let offset = get_offset(resp, kind)?;
+                bitmap::BitFieldOp::IncrBy(kind, offset, get_next_integer(resp)?)
+            }
+            "OVERFLOW" => {
+                let overflow = match get_next_value(resp)?.to_string().to_uppercase().as_ref() {
+                    "WRAP" => bitmap::Overflow::Wrap,
+                    "SAT" => bitmap::Overflow::Sat,
+                    "FAIL" => bitmap::Overflow::Fail,
+                    _ => return Err("ERR Invalid OVERFLOW type specified"),
+                };
+                bitmap::BitFieldOp::Overflow(overflow)
+            }
+            _ => return Err("ERR syntax error"),
+        };
+        operations.push(operation);
+    }
+    Ok(operations)
+}
+
+/// Parse a distance unit of the geo commands as its length in meters
+fn parse_unit(unit: &BulkString) -> Result<f64, &'static str> {
+    match unit.to_string().to_lowercase().as_ref() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err("ERR unsupported unit provided. please use M, KM, FT, MI"),
+    }
+}
+
+/// Parse a stream id `ms-seq`, or `ms` with `seq` as the sequence number
+fn parse_stream_id(id: &str, seq: u64) -> Result<StreamId, &'static str> {
+    let parsed = match id.split_once('-') {
+        Some((ms, seq)) => ms.parse().ok().zip(seq.parse().ok()),
+        None => id.parse().ok().map(|ms| (ms, seq)),
+    };
+    parsed.ok_or(INVALID_STREAM_ID)
+}
+
+/// Parse an end of an XRANGE interval as an inclusive id, `-` and `+` are the lowest and the
+/// highest ids, a time without sequence covers all of them and `(` excludes the id
+fn parse_interval_id(id: &BulkString, start: bool) -> Result<StreamId, &'static str> {
+    let id = id.to_string();
+    let seq = if start { 0 } else { u64::MAX };
+    let id = match id.as_ref() {
+        "-" => return Ok((0, 0)),
+        "+" => return Ok((u64::MAX, u64::MAX)),
+        id => match id.strip_prefix('(') {
+            Some(id) => parse_stream_id(id, seq)?,
+            None => return parse_stream_id(id, seq),
+        },
+    };
+    let (ms, seq) = id;
+    let excluded = if start {
+        match seq.checked_add(1) {
+            Some(seq) => Some((ms, seq)),
+            None => ms.checked_add(1).map(|ms| (ms, 0)),
+        }
+    } else {
+        match seq.checked_sub(1) {
+            Some(seq) => Some((ms, seq)),
+            None => ms.checked_sub(1).map(|ms| (ms, u64::MAX)),
+        }
+    };
+    match (excluded, start) {
+        (Some(id), _) => Ok(id),
+        (None, true) => Err("ERR invalid start ID for the interval"),
+        (None, false) => Err("ERR invalid end ID for the interval"),
+    }
+}
+
+/// Get the next argument as an expiration in seconds, converted to milliseconds
+fn get_next_seconds(resp: &mut VecDeque<RespValue>) -> Result<i64, &'static str> {
+    get_next_integer(resp)?
+        .checked_mul(1000)
+        .ok_or("ERR invalid expire time")
+}
+
+/// Get all the remaining arguments from a RespValue::Array, at least one is required
+fn get_values(resp: &mut VecDeque<RespValue>) -> Result<Vec<BulkString>, &'static str> {
+    if resp.is_empty() {
+        return Err("Not enough arguments");
+    }
+    resp.drain(..)
+        .map(|value| match value {
+            RespValue::BulkString(value) => Ok(value),
+            _ => Err("Invalid argument, must be BulkString"),
+        })
+        .collect()
+}
+
+/// Get all the remaining arguments as key value pairs, at least one pair is required
+fn get_pairs(
+    resp: &mut VecDeque<RespValue>,
+) -> Result<Vec<(BulkString, BulkString)>, &'static str> {
+    if resp.len() % 2 == 1 {
+        return Err("ERR wrong number of arguments");
+    }
+    let mut values = get_values(resp)?.into_iter();
+    let mut pairs = Vec::with_capacity(values.len() / 2);
+    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+        pairs.push((key, value));
+    }
+    Ok(pairs)
+}
+
+/// Get the `FIELDS numfields field [field ...]` arguments of the hash field expiration commands
+fn get_fields(resp: &mut VecDeque<RespValue>) -> Result<Vec<BulkString>, &'static str> {
+    if get_next_value(resp)?.to_string().to_uppercase() != "FIELDS" {
+        return Err("ERR Mandatory argument FIELDS is missing or not at the right position");
+    }
+    let count = get_next_integer(resp)?;
+    if count <= 0 {
+        return Err("ERR Parameter `numFields` should be greater than 0");
+    }
+    if count as usize != resp.len() {
+        return Err("ERR The `numfields` parameter must match the number of arguments");
+    }
+    get_values(resp)
+}
+
 impl TryFrom<RespValue> for RedisCmd {
     type Error = &'static str;
 
@@ -173,35 +2282,768 @@ impl TryFrom<RespValue> for RedisCmd {
                     None => return Err("No command specified"),
                 };
 
-                match cmd.to_string().unwrap_or_default().to_uppercase().as_ref() {
+                let command = cmd.to_string().unwrap_or_default().to_uppercase();
+                match command.as_ref() {
                     "GET" => Ok(RedisCmd::Get(get_next_value(&mut resp)?)),
                     "SET" => Ok(RedisCmd::Set(
                         get_next_value(&mut resp)?,
                         get_next_value(&mut resp)?,
+                        SetOptions::parse(&mut resp)?,
                     )),
-                    "DELETE" => {
-                        // let mut keys = Vec::with_capacity(resp.len());
-                        // keys = resp.into();
-                        Ok(RedisCmd::Delete(
-                            resp.drain(..)
-                                .map(|key| match key {
-                                    RespValue::BulkString(key) => key,
-                                    other => {
-                                        unreachable!(format!("Invalid Type for key: {:?}", other))
+                    // Same as SET with the GET option
+                    "GETSET" => Ok(RedisCmd::Set(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        SetOptions {
+                            get: true,
+                            ..Default::default()
+                        },
+                    )),
+                    "GETEX" => {
+                        let key = get_next_value(&mut resp)?;
+                        let expiration = match resp.len() {
+                            0 => None,
+                            _ => {
+                                let option = get_next_value(&mut resp)?.to_string().to_uppercase();
+                                match option.as_ref() {
+                                    "PERSIST" => Some(None),
+                                    "EX" | "PX" | "EXAT" | "PXAT" => {
+                                        let expiration = Expiration::parse(&option, &mut resp)?;
+                                        if !expiration.is_positive() {
+                                            return Err(
+                                                "ERR invalid expire time in 'getex' command",
+                                            );
+                                        }
+                                        Some(Some(expiration))
                                     }
-                                })
-                                .collect(),
-                        ))
+                                    _ => return Err("ERR syntax error"),
+                                }
+                            }
+                        };
+                        if !resp.is_empty() {
+                            return Err("ERR syntax error");
+                        }
+                        Ok(RedisCmd::GetEx(key, expiration))
                     }
+                    "GETDEL" => Ok(RedisCmd::GetDel(get_next_value(&mut resp)?)),
+                    "MGET" => Ok(RedisCmd::MGet(get_values(&mut resp)?)),
+                    "MSET" => Ok(RedisCmd::MSet(get_pairs(&mut resp)?)),
+                    "MSETNX" => Ok(RedisCmd::MSetNx(get_pairs(&mut resp)?)),
+                    "DEL" | "DELETE" => Ok(RedisCmd::Delete(get_values(&mut resp)?)),
+                    "UNLINK" => Ok(RedisCmd::Unlink(get_values(&mut resp)?)),
                     "APPEND" => Ok(RedisCmd::Append(
                         get_next_value(&mut resp)?,
                         get_next_value(&mut resp)?,
                     )),
+                    "STRLEN" => Ok(RedisCmd::StrLen(get_next_value(&mut resp)?)),
+                    "GETRANGE" | "SUBSTR" => Ok(RedisCmd::GetRange(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "SETRANGE" => Ok(RedisCmd::SetRange(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "SETBIT" => {
+                        let key = get_next_value(&mut resp)?;
+                        let offset = get_bit_offset(&mut resp)?;
+                        let bit = match get_next_value(&mut resp)?.0.as_slice() {
+                            b"0" => false,
+                            b"1" => true,
+                            _ => return Err("ERR bit is not an integer or out of range"),
+                        };
+                        Ok(RedisCmd::SetBit(key, offset, bit))
+                    }
+                    "GETBIT" => Ok(RedisCmd::GetBit(
+                        get_next_value(&mut resp)?,
+                        get_bit_offset(&mut resp)?,
+                    )),
+                    "BITCOUNT" => Ok(RedisCmd::BitCount(
+                        get_next_value(&mut resp)?,
+                        BitRange::parse(&mut resp, true)?,
+                    )),
+                    "BITPOS" => {
+                        let key = get_next_value(&mut resp)?;
+                        let bit = match get_next_value(&mut resp)?.0.as_slice() {
+                            b"0" => false,
+                            b"1" => true,
+                            _ => return Err("ERR The bit argument must be 1 or 0."),
+                        };
+                        Ok(RedisCmd::BitPos(
+                            key,
+                            bit,
+                            BitRange::parse(&mut resp, false)?,
+                        ))
+                    }
+                    "BITOP" => {
+                        let operation = match get_next_value(&mut resp)?
+                            .to_string()
+                            .to_uppercase()
+                            .as_ref()
+                        {
+                            "AND" => bitmap::Operation::And,
+                            "OR" => bitmap::Operation::Or,
+                            "XOR" => bitmap::Operation::Xor,
+                            "NOT" => bitmap::Operation::Not,
+                            _ => return Err("ERR syntax error"),
+                        };
+                        let destination = get_next_value(&mut resp)?;
+                        let keys = get_values(&mut resp)?;
+                        if matches!(operation, bitmap::Operation::Not) && keys.len() > 1 {
+                            return Err("ERR BITOP NOT must be called with a single source key.");
+                        }
+                        Ok(RedisCmd::BitOp(operation, destination, keys))
+                    }
+                    "BITFIELD" | "BITFIELD_RO" => {
+                        let key = get_next_value(&mut resp)?;
+                        let operations = get_bitfield_ops(&mut resp)?;
+                        let read_only = operations
+                            .iter()
+                            .all(|operation| matches!(operation, bitmap::BitFieldOp::Get(..)));
+                        if command == "BITFIELD_RO" && !read_only {
+                            return Err("ERR BITFIELD_RO only supports the GET subcommand");
+                        }
+                        Ok(RedisCmd::BitField(key, operations))
+                    }
+                    "PFADD" => {
+                        let key = get_next_value(&mut resp)?;
+                        let elements = if resp.is_empty() {
+                            vec![]
+                        } else {
+                            get_values(&mut resp)?
+                        };
+                        Ok(RedisCmd::PfAdd(key, elements))
+                    }
+                    "PFCOUNT" => Ok(RedisCmd::PfCount(get_values(&mut resp)?)),
+                    "PFMERGE" => {
+                        let destination = get_next_value(&mut resp)?;
+                        let keys = if resp.is_empty() {
+                            vec![]
+                        } else {
+                            get_values(&mut resp)?
+                        };
+                        Ok(RedisCmd::PfMerge(destination, keys))
+                    }
+                    "INCR" => Ok(RedisCmd::IncrBy(get_next_value(&mut resp)?, 1)),
+                    "DECR" => Ok(RedisCmd::IncrBy(get_next_value(&mut resp)?, -1)),
+                    "INCRBY" => Ok(RedisCmd::IncrBy(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "DECRBY" => Ok(RedisCmd::IncrBy(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?
+                            .checked_neg()
+                            .ok_or("ERR decrement would overflow")?,
+                    )),
+                    "INCRBYFLOAT" => Ok(RedisCmd::IncrByFloat(
+                        get_next_value(&mut resp)?,
+                        parse_float(&get_next_value(&mut resp)?.to_string())?,
+                    )),
                     "PING" => Ok(RedisCmd::Ping(get_next_value(&mut resp).ok())),
                     "KEYS" => Ok(RedisCmd::Keys(get_next_value(&mut resp)?)),
                     "EXISTS" => Ok(RedisCmd::Exists(get_next_value(&mut resp)?)),
-                    "FLUSHALL" => Ok(RedisCmd::FlushAll),
+                    "DUMP" => Ok(RedisCmd::Dump(get_next_value(&mut resp)?)),
+                    "RESTORE" => {
+                        let key = get_next_value(&mut resp)?;
+                        let ttl = get_next_integer(&mut resp)?;
+                        if ttl < 0 {
+                            return Err("ERR Invalid TTL value, must be >= 0");
+                        }
+                        Ok(RedisCmd::Restore(
+                            key,
+                            ttl,
+                            get_next_value(&mut resp)?,
+                            RestoreOptions::parse(&mut resp)?,
+                        ))
+                    }
+                    "OBJECT" => {
+                        let subcommand = match get_next_value(&mut resp)?
+                            .to_string()
+                            .to_uppercase()
+                            .as_ref()
+                        {
+                            "ENCODING" => ObjectSubcommand::Encoding,
+                            "REFCOUNT" => ObjectSubcommand::RefCount,
+                            "IDLETIME" => ObjectSubcommand::IdleTime,
+                            "FREQ" => ObjectSubcommand::Freq,
+                            _ => return Err("ERR unknown OBJECT subcommand"),
+                        };
+                        Ok(RedisCmd::Object(subcommand, get_next_value(&mut resp)?))
+                    }
+                    "TOUCH" => Ok(RedisCmd::Touch(get_values(&mut resp)?)),
+                    "RANDOMKEY" => Ok(RedisCmd::RandomKey),
+                    "DBSIZE" => Ok(RedisCmd::DbSize),
+                    "EXPIRE" => Ok(RedisCmd::Expire(
+                        get_next_value(&mut resp)?,
+                        Expiration::In(get_next_seconds(&mut resp)?),
+                        ExpireOptions::parse(&mut resp, false)?,
+                    )),
+                    "PEXPIRE" => Ok(RedisCmd::Expire(
+                        get_next_value(&mut resp)?,
+                        Expiration::In(get_next_integer(&mut resp)?),
+                        ExpireOptions::parse(&mut resp, false)?,
+                    )),
+                    "EXPIREAT" => Ok(RedisCmd::Expire(
+                        get_next_value(&mut resp)?,
+                        Expiration::At(get_next_seconds(&mut resp)?),
+                        ExpireOptions::parse(&mut resp, false)?,
+                    )),
+                    "PEXPIREAT" => Ok(RedisCmd::Expire(
+                        get_next_value(&mut resp)?,
+                        Expiration::At(get_next_integer(&mut resp)?),
+                        ExpireOptions::parse(&mut resp, false)?,
+                    )),
+                    "TTL" => Ok(RedisCmd::Ttl(get_next_value(&mut resp)?)),
+                    "PTTL" => Ok(RedisCmd::PTtl(get_next_value(&mut resp)?)),
+                    "EXPIRETIME" => Ok(RedisCmd::ExpireTime(get_next_value(&mut resp)?)),
+                    "PEXPIRETIME" => Ok(RedisCmd::PExpireTime(get_next_value(&mut resp)?)),
+                    "PERSIST" => Ok(RedisCmd::Persist(get_next_value(&mut resp)?)),
+                    "HSET" => Ok(RedisCmd::HSet(
+                        get_next_value(&mut resp)?,
+                        get_pairs(&mut resp)?,
+                    )),
+                    "HSETNX" => Ok(RedisCmd::HSetNx(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "HSTRLEN" => Ok(RedisCmd::HStrLen(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "HGET" => Ok(RedisCmd::HGet(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "HMSET" => Ok(RedisCmd::HMSet(
+                        get_next_value(&mut resp)?,
+                        get_pairs(&mut resp)?,
+                    )),
+                    "HMGET" => Ok(RedisCmd::HMGet(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "HDEL" => Ok(RedisCmd::HDel(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "HGETALL" => Ok(RedisCmd::HGetAll(get_next_value(&mut resp)?)),
+                    "HKEYS" => Ok(RedisCmd::HKeys(get_next_value(&mut resp)?)),
+                    "HVALS" => Ok(RedisCmd::HVals(get_next_value(&mut resp)?)),
+                    "HLEN" => Ok(RedisCmd::HLen(get_next_value(&mut resp)?)),
+                    "HEXISTS" => Ok(RedisCmd::HExists(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "HSCAN" => Ok(RedisCmd::HScan(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?
+                            .to_string()
+                            .parse()
+                            .map_err(|_| "ERR invalid cursor")?,
+                        ScanOptions::parse(&mut resp)?,
+                    )),
+                    "HRANDFIELD" => {
+                        let key = get_next_value(&mut resp)?;
+                        let (count, with_values) = get_random_count(&mut resp, "WITHVALUES")?;
+                        Ok(RedisCmd::HRandField(key, count, with_values))
+                    }
+                    "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" => {
+                        let key = get_next_value(&mut resp)?;
+                        let expiration = match command.as_ref() {
+                            "HEXPIRE" => Expiration::In(get_next_seconds(&mut resp)?),
+                            "HPEXPIRE" => Expiration::In(get_next_integer(&mut resp)?),
+                            "HEXPIREAT" => Expiration::At(get_next_seconds(&mut resp)?),
+                            _ => Expiration::At(get_next_integer(&mut resp)?),
+                        };
+                        Ok(RedisCmd::HExpire(
+                            key,
+                            expiration,
+                            ExpireOptions::parse(&mut resp, true)?,
+                            get_fields(&mut resp)?,
+                        ))
+                    }
+                    "HTTL" => Ok(RedisCmd::HTtl(
+                        get_next_value(&mut resp)?,
+                        get_fields(&mut resp)?,
+                    )),
+                    "HPTTL" => Ok(RedisCmd::HPTtl(
+                        get_next_value(&mut resp)?,
+                        get_fields(&mut resp)?,
+                    )),
+                    "HPERSIST" => Ok(RedisCmd::HPersist(
+                        get_next_value(&mut resp)?,
+                        get_fields(&mut resp)?,
+                    )),
+                    "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" => Ok(RedisCmd::Push(
+                        get_next_value(&mut resp)?,
+                        if command.starts_with('L') {
+                            Side::Left
+                        } else {
+                            Side::Right
+                        },
+                        command.ends_with('X'),
+                        get_values(&mut resp)?,
+                    )),
+                    "LPOP" | "RPOP" => {
+                        let key = get_next_value(&mut resp)?;
+                        let count = match resp.len() {
+                            0 => None,
+                            1 => match get_next_integer(&mut resp)? {
+                                count if count >= 0 => Some(count as usize),
+                                _ => return Err("ERR value is out of range, must be positive"),
+                            },
+                            _ => return Err("ERR wrong number of arguments"),
+                        };
+                        let side = match command.as_ref() {
+                            "LPOP" => Side::Left,
+                            _ => Side::Right,
+                        };
+                        Ok(RedisCmd::Pop(key, side, count))
+                    }
+                    "LLEN" => Ok(RedisCmd::LLen(get_next_value(&mut resp)?)),
+                    "LRANGE" => Ok(RedisCmd::LRange(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "LINDEX" => Ok(RedisCmd::LIndex(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "LSET" => Ok(RedisCmd::LSet(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "LINSERT" => Ok(RedisCmd::LInsert(
+                        get_next_value(&mut resp)?,
+                        match get_next_value(&mut resp)?
+                            .to_string()
+                            .to_uppercase()
+                            .as_ref()
+                        {
+                            "BEFORE" => true,
+                            "AFTER" => false,
+                            _ => return Err("ERR syntax error"),
+                        },
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "LREM" => Ok(RedisCmd::LRem(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "LTRIM" => Ok(RedisCmd::LTrim(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "LPOS" => Ok(RedisCmd::LPos(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        LPosOptions::parse(&mut resp)?,
+                    )),
+                    // Same as LMOVE from the right to the left
+                    "RPOPLPUSH" => Ok(RedisCmd::LMove(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        Side::Right,
+                        Side::Left,
+                    )),
+                    "LMOVE" => Ok(RedisCmd::LMove(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        get_side(&mut resp)?,
+                        get_side(&mut resp)?,
+                    )),
+                    "BLPOP" | "BRPOP" => {
+                        let timeout = get_timeout(&mut resp)?;
+                        let side = match command.as_ref() {
+                            "BLPOP" => Side::Left,
+                            _ => Side::Right,
+                        };
+                        Ok(RedisCmd::BPop(get_values(&mut resp)?, side, timeout))
+                    }
+                    "BRPOPLPUSH" => {
+                        let timeout = get_timeout(&mut resp)?;
+                        Ok(RedisCmd::BLMove(
+                            get_next_value(&mut resp)?,
+                            get_next_value(&mut resp)?,
+                            Side::Right,
+                            Side::Left,
+                            timeout,
+                        ))
+                    }
+                    "BLMOVE" => {
+                        let timeout = get_timeout(&mut resp)?;
+                        Ok(RedisCmd::BLMove(
+                            get_next_value(&mut resp)?,
+                            get_next_value(&mut resp)?,
+                            get_side(&mut resp)?,
+                            get_side(&mut resp)?,
+                            timeout,
+                        ))
+                    }
+                    "LMPOP" => {
+                        let (keys, side, count) = get_mpop(&mut resp, get_side)?;
+                        Ok(RedisCmd::LMPop(keys, side, count))
+                    }
+                    "BLMPOP" => {
+                        let timeout = parse_timeout(&get_next_value(&mut resp)?)?;
+                        let (keys, side, count) = get_mpop(&mut resp, get_side)?;
+                        Ok(RedisCmd::BLMPop(keys, side, count, timeout))
+                    }
+                    "SADD" => Ok(RedisCmd::SAdd(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "SREM" => Ok(RedisCmd::SRem(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "SMEMBERS" => Ok(RedisCmd::SMembers(get_next_value(&mut resp)?)),
+                    "SISMEMBER" => Ok(RedisCmd::SIsMember(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "SMISMEMBER" => Ok(RedisCmd::SMIsMember(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "SCARD" => Ok(RedisCmd::SCard(get_next_value(&mut resp)?)),
+                    "SMOVE" => Ok(RedisCmd::SMove(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "SINTERCARD" => {
+                        let count = get_next_integer(&mut resp)?;
+                        if count <= 0 {
+                            return Err("ERR numkeys should be greater than 0");
+                        }
+                        if count as usize > resp.len() {
+                            return Err("ERR Number of keys can't be greater than number of args");
+                        }
+                        let mut options = resp.split_off(count as usize);
+                        let limit = match options.len() {
+                            0 => 0,
+                            2 if get_next_value(&mut options)?.to_string().to_uppercase()
+                                == "LIMIT" =>
+                            {
+                                match get_next_integer(&mut options)? {
+                                    limit if limit >= 0 => limit as usize,
+                                    _ => return Err("ERR LIMIT can't be negative"),
+                                }
+                            }
+                            _ => return Err("ERR syntax error"),
+                        };
+                        Ok(RedisCmd::SInterCard(get_values(&mut resp)?, limit))
+                    }
+                    "SINTER" | "SUNION" | "SDIFF" | "SINTERSTORE" | "SUNIONSTORE"
+                    | "SDIFFSTORE" => {
+                        let operation = match command.trim_end_matches("STORE") {
+                            "SINTER" => set::Operation::Inter,
+                            "SUNION" => set::Operation::Union,
+                            _ => set::Operation::Diff,
+                        };
+                        let destination = if command.ends_with("STORE") {
+                            Some(get_next_value(&mut resp)?)
+                        } else {
+                            None
+                        };
+                        Ok(RedisCmd::SCombine(
+                            operation,
+                            destination,
+                            get_values(&mut resp)?,
+                        ))
+                    }
+                    "ZADD" => {
+                        let key = get_next_value(&mut resp)?;
+                        let options = ZAddOptions::parse(&mut resp)?;
+                        let members = get_pairs(&mut resp)?
+                            .into_iter()
+                            .map(|(score, member)| Ok((parse_score(&score)?, member)))
+                            .collect::<Result<Vec<_>, &'static str>>()?;
+                        if options.incr && members.len() > 1 {
+                            return Err("ERR INCR option supports a single increment-element pair");
+                        }
+                        Ok(RedisCmd::ZAdd(key, options, members))
+                    }
+                    // Same as ZADD with the INCR option
+                    "ZINCRBY" => {
+                        let key = get_next_value(&mut resp)?;
+                        let increment = parse_score(&get_next_value(&mut resp)?)?;
+                        let options = ZAddOptions {
+                            incr: true,
+                            ..Default::default()
+                        };
+                        let member = get_next_value(&mut resp)?;
+                        Ok(RedisCmd::ZAdd(key, options, vec![(increment, member)]))
+                    }
+                    // Positions are members of a sorted set with their geohash as the score
+                    "GEOADD" => {
+                        let key = get_next_value(&mut resp)?;
+                        let options = ZAddOptions::parse(&mut resp)?;
+                        if options.gt || options.lt || options.incr {
+                            return Err("ERR syntax error");
+                        }
+                        if resp.is_empty() || resp.len() % 3 != 0 {
+                            return Err("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ");
+                        }
+                        let mut members = Vec::with_capacity(resp.len() / 3);
+                        while !resp.is_empty() {
+                            let longitude = parse_score(&get_next_value(&mut resp)?)?;
+                            let latitude = parse_score(&get_next_value(&mut resp)?)?;
+                            let member = get_next_value(&mut resp)?;
+                            members.push((geo::encode(longitude, latitude)?, member));
+                        }
+                        Ok(RedisCmd::ZAdd(key, options, members))
+                    }
+                    "XADD" => {
+                        let key = get_next_value(&mut resp)?;
+                        let options = XAddOptions::parse(&mut resp)?;
+                        let id = match get_next_value(&mut resp)?.to_string().as_ref() {
+                            "*" => NewId::Auto,
+                            id => match id.strip_suffix("-*") {
+                                Some(ms) => {
+                                    NewId::Sequence(ms.parse().map_err(|_| INVALID_STREAM_ID)?)
+                                }
+                                None => NewId::Explicit(parse_stream_id(id, 0)?),
+                            },
+                        };
+                        if resp.is_empty() {
+                            return Err("ERR wrong number of arguments");
+                        }
+                        Ok(RedisCmd::XAdd(key, options, id, get_pairs(&mut resp)?))
+                    }
+                    "XLEN" => Ok(RedisCmd::XLen(get_next_value(&mut resp)?)),
+                    "XRANGE" | "XREVRANGE" => {
+                        let key = get_next_value(&mut resp)?;
+                        let rev = command == "XREVRANGE";
+                        let (first, second) =
+                            (get_next_value(&mut resp)?, get_next_value(&mut resp)?);
+                        let (start, end) = if rev {
+                            (second, first)
+                        } else {
+                            (first, second)
+                        };
+                        let start = parse_interval_id(&start, true)?;
+                        let end = parse_interval_id(&end, false)?;
+                        let count = match resp.len() {
+                            0 => None,
+                            2 if get_next_value(&mut resp)?.to_string().to_uppercase()
+                                == "COUNT" =>
+                            {
+                                Some(get_next_integer(&mut resp)?.max(0) as usize)
+                            }
+                            _ => return Err("ERR syntax error"),
+                        };
+                        Ok(RedisCmd::XRange(key, start, end, count, rev))
+                    }
+                    "XREAD" => {
+                        let (mut count, mut block, mut timeout) = (None, false, None);
+                        loop {
+                            match get_next_value(&mut resp)?
+                                .to_string()
+                                .to_uppercase()
+                                .as_ref()
+                            {
+                                "COUNT" => {
+                                    count = Some(get_next_integer(&mut resp)?.max(0) as usize)
+                                }
+                                "BLOCK" => {
+                                    block = true;
+                                    let millis = get_next_integer(&mut resp)?;
+                                    timeout = timeout_from_millis(millis as f64)?;
+                                }
+                                "STREAMS" => break,
+                                _ => return Err("ERR syntax error"),
+                            }
+                        }
+                        if resp.is_empty() || resp.len() % 2 != 0 {
+                            return Err("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.");
+                        }
+                        let ids = resp
+                            .split_off(resp.len() / 2)
+                            .into_iter()
+                            .map(|id| match id {
+                                RespValue::BulkString(id) if id.0 == b"$" => Ok(None),
+                                RespValue::BulkString(id) => {
+                                    parse_stream_id(&id.to_string(), 0).map(Some)
+                                }
+                                _ => Err("Invalid argument, must be BulkString"),
+                            })
+                            .collect::<Result<_, _>>()?;
+                        Ok(RedisCmd::XRead(
+                            get_values(&mut resp)?,
+                            ids,
+                            count,
+                            block,
+                            timeout,
+                        ))
+                    }
+                    "GEOPOS" => {
+                        let key = get_next_value(&mut resp)?;
+                        let members = if resp.is_empty() {
+                            vec![]
+                        } else {
+                            get_values(&mut resp)?
+                        };
+                        Ok(RedisCmd::GeoPos(key, members))
+                    }
+                    "GEODIST" => {
+                        let key = get_next_value(&mut resp)?;
+                        let first = get_next_value(&mut resp)?;
+                        let second = get_next_value(&mut resp)?;
+                        let unit = match resp.len() {
+                            0 => 1.0,
+                            1 => parse_unit(&get_next_value(&mut resp)?)?,
+                            _ => return Err("ERR syntax error"),
+                        };
+                        Ok(RedisCmd::GeoDist(key, first, second, unit))
+                    }
+                    "GEOSEARCH" | "GEOSEARCHSTORE" => {
+                        let store = command == "GEOSEARCHSTORE";
+                        let destination = if store {
+                            Some(get_next_value(&mut resp)?)
+                        } else {
+                            None
+                        };
+                        let key = get_next_value(&mut resp)?;
+                        let options = GeoSearchOptions::parse(&mut resp, store)?;
+                        Ok(RedisCmd::GeoSearch(destination, key, options))
+                    }
+                    "ZSCORE" => Ok(RedisCmd::ZScore(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                    )),
+                    "ZREM" => Ok(RedisCmd::ZRem(
+                        get_next_value(&mut resp)?,
+                        get_values(&mut resp)?,
+                    )),
+                    "ZCARD" => Ok(RedisCmd::ZCard(get_next_value(&mut resp)?)),
+                    "ZRANDMEMBER" => {
+                        let key = get_next_value(&mut resp)?;
+                        let (count, with_scores) = get_random_count(&mut resp, "WITHSCORES")?;
+                        Ok(RedisCmd::ZRandMember(key, count, with_scores))
+                    }
+                    "ZMPOP" => {
+                        let (keys, max, count) = get_mpop(&mut resp, get_min_max)?;
+                        Ok(RedisCmd::ZMPop(keys, max, count))
+                    }
+                    "BZMPOP" => {
+                        let timeout = parse_timeout(&get_next_value(&mut resp)?)?;
+                        let (keys, max, count) = get_mpop(&mut resp, get_min_max)?;
+                        Ok(RedisCmd::BZMPop(keys, max, count, timeout))
+                    }
+                    "ZRANGE" | "ZREVRANGE" | "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE"
+                    | "ZRANGEBYLEX" | "ZREVRANGEBYLEX" => Ok(RedisCmd::ZRange(
+                        get_next_value(&mut resp)?,
+                        ZRangeOptions::parse(&command, &mut resp)?,
+                    )),
+                    "ZRANGESTORE" => Ok(RedisCmd::ZRangeStore(
+                        get_next_value(&mut resp)?,
+                        get_next_value(&mut resp)?,
+                        ZRangeOptions::parse(&command, &mut resp)?,
+                    )),
+                    "ZUNION" | "ZINTER" | "ZDIFF" | "ZUNIONSTORE" | "ZINTERSTORE"
+                    | "ZDIFFSTORE" => {
+                        let operation = match command.trim_end_matches("STORE") {
+                            "ZINTER" => set::Operation::Inter,
+                            "ZUNION" => set::Operation::Union,
+                            _ => set::Operation::Diff,
+                        };
+                        let destination = if command.ends_with("STORE") {
+                            Some(get_next_value(&mut resp)?)
+                        } else {
+                            None
+                        };
+                        let count = get_next_integer(&mut resp)?;
+                        if count <= 0 {
+                            return Err("ERR at least 1 input key is needed");
+                        }
+                        if count as usize > resp.len() {
+                            return Err("ERR syntax error");
+                        }
+                        let mut options = resp.split_off(count as usize);
+                        let keys = get_values(&mut resp)?;
+                        let options = ZCombineOptions::parse(
+                            &mut options,
+                            keys.len(),
+                            operation,
+                            destination.is_some(),
+                        )?;
+                        Ok(RedisCmd::ZCombine(operation, destination, keys, options))
+                    }
+                    "ZCOUNT" => Ok(RedisCmd::ZCount(
+                        get_next_value(&mut resp)?,
+                        ZRangeBy::Score(
+                            parse_score_bound(&get_next_value(&mut resp)?)?,
+                            parse_score_bound(&get_next_value(&mut resp)?)?,
+                        ),
+                    )),
+                    "ZLEXCOUNT" => Ok(RedisCmd::ZCount(
+                        get_next_value(&mut resp)?,
+                        ZRangeBy::Lex(
+                            parse_lex_bound(get_next_value(&mut resp)?)?,
+                            parse_lex_bound(get_next_value(&mut resp)?)?,
+                        ),
+                    )),
+                    "ZRANK" | "ZREVRANK" => {
+                        let key = get_next_value(&mut resp)?;
+                        let member = get_next_value(&mut resp)?;
+                        let with_score = match get_next_value(&mut resp) {
+                            Ok(option) if option.to_string().to_uppercase() == "WITHSCORE" => true,
+                            Ok(_) => return Err("ERR syntax error"),
+                            Err(_) => false,
+                        };
+                        if !resp.is_empty() {
+                            return Err("ERR syntax error");
+                        }
+                        Ok(RedisCmd::ZRank(
+                            key,
+                            member,
+                            command == "ZREVRANK",
+                            with_score,
+                        ))
+                    }
+                    "SELECT" => Ok(RedisCmd::Select(get_next_integer(&mut resp)?)),
+                    "MOVE" => Ok(RedisCmd::Move(
+                        get_next_value(&mut resp)?,
+                        get_next_integer(&mut resp)?,
+                    )),
+                    "SWAPDB" => Ok(RedisCmd::SwapDb(
+                        get_next_value(&mut resp)?
+                            .to_string()
+                            .parse()
+                            .map_err(|_| "ERR invalid first DB index")?,
+                        get_next_value(&mut resp)?
+                            .to_string()
+                            .parse()
+                            .map_err(|_| "ERR invalid second DB index")?,
+                    )),
+                    "FLUSHDB" => Ok(RedisCmd::FlushDb(get_flush_mode(&mut resp)?)),
+                    "FLUSHALL" => Ok(RedisCmd::FlushAll(get_flush_mode(&mut resp)?)),
                     "COMMAND" => Ok(RedisCmd::Command),
+                    #[cfg(feature = "failpoints")]
+                    "DEBUG" => {
+                        let subcommand = get_next_value(&mut resp)?.to_string();
+                        if subcommand.to_uppercase() != "FAILPOINT" {
+                            return Err("ERR unknown DEBUG subcommand");
+                        }
+                        let command = get_next_value(&mut resp)?.to_string();
+                        let action = get_next_value(&mut resp)?.to_string();
+                        let arg = get_next_value(&mut resp).ok().map(|arg| arg.to_string());
+                        Ok(RedisCmd::Failpoint(
+                            command,
+                            Failpoint::parse(&action, arg)?,
+                        ))
+                    }
                     "" => Err("No command specified"),
                     _ => Err("Invalid command"),
                 }
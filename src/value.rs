@@ -0,0 +1,346 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{Hash as _, Hasher};
+use std::ops::Deref;
+
+use crate::types::BulkString;
+
+/// Error replied when a command is used on a key holding another type
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Id of a stream entry, milliseconds and sequence number
+pub type StreamId = (u64, u64);
+
+/// Value stored for a key, commands check the type before using it
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(BulkString),
+    List(VecDeque<BulkString>),
+    Hash(Hash),
+    Set(HashSet<BulkString>),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// Internal representation of the value, as shown by OBJECT ENCODING
+    /// Names are the ones used by redis for the same structures
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(_) => "raw",
+            Value::List(_) => "linkedlist",
+            Value::Hash(_) | Value::Set(_) => "hashtable",
+            Value::ZSet(_) => "skiplist",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// The string stored, or WRONGTYPE
+    pub fn as_string(&self) -> Result<&BulkString, &'static str> {
+        match self {
+            Value::String(value) => Ok(value),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Result<&mut BulkString, &'static str> {
+        match self {
+            Value::String(value) => Ok(value),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_hash(&self) -> Result<&Hash, &'static str> {
+        match self {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Result<&mut Hash, &'static str> {
+        match self {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&VecDeque<BulkString>, &'static str> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Result<&mut VecDeque<BulkString>, &'static str> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_set(&self) -> Result<&HashSet<BulkString>, &'static str> {
+        match self {
+            Value::Set(set) => Ok(set),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_set_mut(&mut self) -> Result<&mut HashSet<BulkString>, &'static str> {
+        match self {
+            Value::Set(set) => Ok(set),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_zset(&self) -> Result<&SortedSet, &'static str> {
+        match self {
+            Value::ZSet(zset) => Ok(zset),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_zset_mut(&mut self) -> Result<&mut SortedSet, &'static str> {
+        match self {
+            Value::ZSet(zset) => Ok(zset),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_stream(&self) -> Result<&Stream, &'static str> {
+        match self {
+            Value::Stream(stream) => Ok(stream),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    pub fn as_stream_mut(&mut self) -> Result<&mut Stream, &'static str> {
+        match self {
+            Value::Stream(stream) => Ok(stream),
+            _ => Err(WRONGTYPE),
+        }
+    }
+
+    /// Collections without elements, their key is removed like in redis
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) | Value::Stream(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+impl From<BulkString> for Value {
+    fn from(value: BulkString) -> Value {
+        Value::String(value)
+    }
+}
+
+/// Fields of a hash, each one can have its own expiration
+/// Reads go through the map of fields, writes through `insert` and `remove` so the expiration
+/// of a field is discarded when it's overwritten or removed
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: HashMap<BulkString, BulkString>,
+    /// Unix time in milliseconds when fields expire
+    expirations: HashMap<BulkString, i64>,
+    /// Same expirations sorted by time, to find the expired fields without checking all of them
+    deadlines: BTreeSet<(i64, BulkString)>,
+    /// Fields sorted by `scan_position`, so HSCAN resumes from its cursor without visiting the
+    /// fields before it
+    scan_order: BTreeSet<(u64, BulkString)>,
+}
+
+/// Position of a field in the iteration of HSCAN, a hash that doesn't change between calls
+fn scan_position(field: &BulkString) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    field.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Hash {
+    pub fn insert(&mut self, field: BulkString, value: BulkString) -> Option<BulkString> {
+        self.set_expiration(&field, None);
+        if !self.fields.contains_key(&field) {
+            self.scan_order
+                .insert((scan_position(&field), field.clone()));
+        }
+        self.fields.insert(field, value)
+    }
+
+    pub fn remove(&mut self, field: &BulkString) -> Option<BulkString> {
+        self.set_expiration(field, None);
+        let removed = self.fields.remove(field)?;
+        self.scan_order
+            .remove(&(scan_position(field), field.clone()));
+        Some(removed)
+    }
+
+    /// Fields from the scan position `cursor` on, with their position, in the order of HSCAN
+    pub fn scan(&self, cursor: u64) -> impl Iterator<Item = (u64, &BulkString)> {
+        self.scan_order
+            .range((cursor, BulkString(vec![]))..)
+            .map(|(position, field)| (*position, field))
+    }
+
+    /// Unix time in milliseconds when a field expires
+    pub fn expiration(&self, field: &BulkString) -> Option<i64> {
+        self.expirations.get(field).copied()
+    }
+
+    /// Change the expiration of an existing field, `None` removes it
+    pub fn set_expiration(&mut self, field: &BulkString, expires_at: Option<i64>) {
+        if let Some(previous) = self.expirations.remove(field) {
+            self.deadlines.remove(&(previous, field.clone()));
+        }
+        if let Some(expires_at) = expires_at {
+            self.expirations.insert(field.clone(), expires_at);
+            self.deadlines.insert((expires_at, field.clone()));
+        }
+    }
+
+    /// Some field has an expiration
+    pub fn has_expirations(&self) -> bool {
+        !self.deadlines.is_empty()
+    }
+
+    /// Unix time in milliseconds when the next field expires
+    pub fn next_expiration(&self) -> Option<i64> {
+        self.deadlines
+            .iter()
+            .next()
+            .map(|(expires_at, _)| *expires_at)
+    }
+
+    /// Remove the fields expired at `now`, returns how many were removed
+    pub fn remove_expired(&mut self, now: i64) -> usize {
+        let mut removed = 0;
+        while let Some((expires_at, field)) = self.deadlines.iter().next().cloned() {
+            if expires_at > now {
+                break;
+            }
+            self.remove(&field);
+            removed += 1;
+        }
+        removed
+    }
+}
+
+impl Deref for Hash {
+    type Target = HashMap<BulkString, BulkString>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl From<HashMap<BulkString, BulkString>> for Hash {
+    fn from(fields: HashMap<BulkString, BulkString>) -> Hash {
+        let scan_order = fields
+            .keys()
+            .map(|field| (scan_position(field), field.clone()))
+            .collect();
+        Hash {
+            fields,
+            scan_order,
+            ..Default::default()
+        }
+    }
+}
+
+/// Score of a member of a sorted set, totally ordered because NaN is never stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Members of a sorted set with their scores
+/// Reads go through the map of scores, or `ordered` to get them sorted, writes through
+/// `insert` and `remove` to keep both in sync
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<BulkString, f64>,
+    /// Members sorted by score, then by member
+    ordered: BTreeSet<(Score, BulkString)>,
+}
+
+impl SortedSet {
+    /// Set the score of a member, returns the previous one
+    pub fn insert(&mut self, member: BulkString, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous
+    }
+
+    pub fn remove(&mut self, member: &BulkString) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.ordered.remove(&(Score(score), member.clone()));
+        Some(score)
+    }
+
+    pub fn ordered(&self) -> &BTreeSet<(Score, BulkString)> {
+        &self.ordered
+    }
+}
+
+impl Deref for SortedSet {
+    type Target = HashMap<BulkString, f64>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.scores
+    }
+}
+
+/// Entries of a stream with their field-value pairs, sorted by id
+/// Reads go through the map of entries, writes through `insert` and `pop_first` so the last id
+/// is kept when the entries are trimmed, new ids must be greater than it
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(BulkString, BulkString)>>,
+    last_id: StreamId,
+}
+
+impl Stream {
+    /// Id of the last entry added, even if it was removed
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Add an entry, the id has to be greater than the last one
+    pub fn insert(&mut self, id: StreamId, fields: Vec<(BulkString, BulkString)>) {
+        self.last_id = id;
+        self.entries.insert(id, fields);
+    }
+
+    /// Remove the oldest entry
+    pub fn pop_first(&mut self) -> Option<(StreamId, Vec<(BulkString, BulkString)>)> {
+        let first = *self.entries.keys().next()?;
+        self.entries.remove_entry(&first)
+    }
+}
+
+impl Deref for Stream {
+    type Target = BTreeMap<StreamId, Vec<(BulkString, BulkString)>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
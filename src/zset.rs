@@ -0,0 +1,503 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Bound;
+
+use rand::seq::IteratorRandom;
+
+use crate::list;
+use crate::random;
+use crate::set::Operation;
+use crate::storage::Storage;
+use crate::types::{
+    BulkString, RedisKey, RespValue, ZAddOptions, ZCombineOptions, ZRangeBy, ZRangeOptions,
+};
+use crate::value::{Score, SortedSet, Value, WRONGTYPE};
+
+/// Score formatted as a reply, infinities are `inf` and `-inf` like in redis
+fn score_reply(score: f64) -> RespValue {
+    RespValue::BulkString(BulkString(score.to_string().into_bytes()))
+}
+
+/// Add members or update their scores, creating the sorted set if needed
+/// Replies the number of new members, also counting the updated ones with CH, or the new score
+/// with INCR (null if the conditions prevented the update)
+pub fn zadd(
+    storage: &mut Storage,
+    key: &RedisKey,
+    options: &ZAddOptions,
+    members: Vec<(f64, BulkString)>,
+) -> Result<RespValue, &'static str> {
+    let zset = if options.xx {
+        // Only existing members are updated, the key is never created
+        match storage.get_mut(key) {
+            Some(entry) => entry.value.as_zset_mut()?,
+            None if options.incr => return Ok(RespValue::Null),
+            None => return Ok(RespValue::Integer(0)),
+        }
+    } else {
+        storage
+            .get_or_insert_with(key, || Value::ZSet(SortedSet::default()))
+            .as_zset_mut()?
+    };
+    let mut added = 0;
+    let mut changed = 0;
+    let mut result = Ok(None);
+    for (score, member) in members {
+        let current = zset.get(&member).copied();
+        let score = match current {
+            Some(current) if options.incr => current + score,
+            _ => score,
+        };
+        if score.is_nan() {
+            result = Err("ERR resulting score is not a number (NaN)");
+            break;
+        }
+        if !options.allows(current, score) {
+            continue;
+        }
+        match current {
+            None => added += 1,
+            Some(current) if current != score => changed += 1,
+            Some(_) => {}
+        }
+        zset.insert(member, score);
+        result = Ok(Some(score));
+    }
+    // The key was created but nothing was added
+    storage.remove_if_empty(key);
+    let score = result?;
+    if options.incr {
+        return Ok(score.map_or(RespValue::Null, score_reply));
+    }
+    if options.ch {
+        added += changed;
+    }
+    Ok(RespValue::Integer(added))
+}
+
+/// Score of a member, null if it doesn't exist
+pub fn zscore(
+    storage: &mut Storage,
+    key: &RedisKey,
+    member: &BulkString,
+) -> Result<RespValue, &'static str> {
+    let score = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?.get(member).copied(),
+        None => None,
+    };
+    Ok(score.map_or(RespValue::Null, score_reply))
+}
+
+/// Position of a member by score, from the highest with `rev`
+/// Replies null for missing members, with `with_score` the rank and score are replied together
+pub fn zrank(
+    storage: &mut Storage,
+    key: &RedisKey,
+    member: &BulkString,
+    rev: bool,
+    with_score: bool,
+) -> Result<RespValue, &'static str> {
+    let missing = if with_score {
+        RespValue::NullArray
+    } else {
+        RespValue::Null
+    };
+    let zset = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?,
+        None => return Ok(missing),
+    };
+    let score = match zset.get(member) {
+        Some(score) => *score,
+        None => return Ok(missing),
+    };
+    let mut rank = zset
+        .ordered()
+        .range(..(Score(score), member.clone()))
+        .count();
+    if rev {
+        rank = zset.len() - 1 - rank;
+    }
+    if !with_score {
+        return Ok(RespValue::Integer(rank as i64));
+    }
+    let reply = vec![RespValue::Integer(rank as i64), score_reply(score)];
+    Ok(RespValue::Array(reply.into()))
+}
+
+/// Remove members of a sorted set, the key is removed with the last member
+pub fn zrem(
+    storage: &mut Storage,
+    key: &RedisKey,
+    members: &[BulkString],
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get_mut(key) {
+        Some(entry) => entry.value.as_zset_mut()?,
+        None => return Ok(RespValue::Integer(0)),
+    };
+    let removed = members
+        .iter()
+        .filter(|member| zset.remove(member).is_some())
+        .count();
+    storage.remove_if_empty(key);
+    Ok(RespValue::Integer(removed as i64))
+}
+
+/// Pop up to `count` members with the lowest scores, or the highest with `max`, from the first
+/// sorted set that exists
+/// Replies the key and the members with their scores, or a null array if no sorted set exists
+pub fn zmpop(
+    storage: &mut Storage,
+    keys: &[RedisKey],
+    max: bool,
+    count: usize,
+) -> Result<RespValue, &'static str> {
+    for key in keys {
+        let zset = match storage.get_mut(key) {
+            Some(entry) => entry.value.as_zset_mut()?,
+            None => continue,
+        };
+        let popped: Vec<_> = if max {
+            zset.ordered().iter().rev().take(count).cloned().collect()
+        } else {
+            zset.ordered().iter().take(count).cloned().collect()
+        };
+        let mut members = VecDeque::new();
+        for (Score(score), member) in popped {
+            zset.remove(&member);
+            let pair = vec![RespValue::BulkString(member), score_reply(score)];
+            members.push_back(RespValue::Array(pair.into()));
+        }
+        storage.remove_if_empty(key);
+        let reply = vec![
+            RespValue::BulkString(key.clone()),
+            RespValue::Array(members),
+        ];
+        return Ok(RespValue::Array(reply.into()));
+    }
+    Ok(RespValue::NullArray)
+}
+
+/// Number of members of a sorted set
+pub fn zcard(storage: &mut Storage, key: &RedisKey) -> Result<RespValue, &'static str> {
+    let len = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?.len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Random members of a sorted set, like HRANDFIELD
+/// Without count a single member is replied, or null. A positive count replies distinct members,
+/// up to the size of the sorted set, and a negative count replies that many members, maybe repeated
+pub fn zrandmember(
+    storage: &mut Storage,
+    key: &RedisKey,
+    count: Option<i64>,
+    with_scores: bool,
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?,
+        None if count.is_none() => return Ok(RespValue::Null),
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    let mut rng = rand::thread_rng();
+    let count = match count {
+        Some(count) => count,
+        None => {
+            return Ok(zset
+                .keys()
+                .choose(&mut rng)
+                .map_or(RespValue::Null, |member| {
+                    RespValue::BulkString(member.clone())
+                }))
+        }
+    };
+
+    let mut elements = VecDeque::new();
+    for (member, score) in random::sample(zset.iter(), count) {
+        elements.push_back(RespValue::BulkString(member.clone()));
+        if with_scores {
+            elements.push_back(score_reply(*score));
+        }
+    }
+    Ok(RespValue::Array(elements))
+}
+
+/// Members selected by a range, with their scores if asked
+pub fn zrange(
+    storage: &mut Storage,
+    key: &RedisKey,
+    options: &ZRangeOptions,
+) -> Result<RespValue, &'static str> {
+    let zset = match storage.get(key) {
+        Some(entry) => entry.value.as_zset()?,
+        None => return Ok(RespValue::Array(VecDeque::new())),
+    };
+    let mut reply = VecDeque::new();
+    for (Score(score), member) in select(zset, &options.by, options.rev, options.limit) {
+        reply.push_back(RespValue::BulkString(member.clone()));
+        if options.with_scores {
+            reply.push_back(score_reply(*score));
+        }
+    }
+    Ok(RespValue::Array(reply))
+}
+
+/// Store the members selected by a range, replacing the destination
+/// Replies the number of members stored, the destination is removed if there are none
+pub fn zrangestore(
+    storage: &mut Storage,
+    destination: &RedisKey,
+    source: &RedisKey,
+    options: &ZRangeOptions,
+) -> Result<RespValue, &'static str> {
+    let mut zset = SortedSet::default();
+    if let Some(entry) = storage.get(source) {
+        let source = entry.value.as_zset()?;
+        for (Score(score), member) in select(source, &options.by, options.rev, options.limit) {
+            zset.insert(member.clone(), *score);
+        }
+    }
+    let len = zset.len();
+    if zset.is_empty() {
+        storage.remove(destination);
+    } else {
+        storage.set(destination.clone(), Value::ZSet(zset));
+    }
+    Ok(RespValue::Integer(len as i64))
+}
+
+/// Number of members in a range of scores or members
+pub fn zcount(
+    storage: &mut Storage,
+    key: &RedisKey,
+    by: &ZRangeBy,
+) -> Result<RespValue, &'static str> {
+    let count = match storage.get(key) {
+        Some(entry) => select(entry.value.as_zset()?, by, false, None).len(),
+        None => 0,
+    };
+    Ok(RespValue::Integer(count as i64))
+}
+
+/// Members of a sorted set selected by a range, in the order of the range
+/// `rev` and `limit` are the options of ZRANGE
+fn select<'a>(
+    zset: &'a SortedSet,
+    by: &ZRangeBy,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+) -> Vec<&'a (Score, BulkString)> {
+    let ordered = zset.ordered();
+    let mut members: Vec<_> = match by {
+        ZRangeBy::Rank(start, end) => {
+            // Ranks of reversed ranges start from the highest score
+            return match list::range(ordered.len(), *start, *end) {
+                Some((start, end)) if rev => ordered
+                    .iter()
+                    .rev()
+                    .skip(start)
+                    .take(end - start + 1)
+                    .collect(),
+                Some((start, end)) => ordered.iter().skip(start).take(end - start + 1).collect(),
+                None => Vec::new(),
+            };
+        }
+        ZRangeBy::Score(min, max) => {
+            let lowest = match min {
+                Bound::Included(score) | Bound::Excluded(score) => *score,
+                Bound::Unbounded => f64::NEG_INFINITY,
+            };
+            ordered
+                .range((Score(lowest), BulkString(Vec::new()))..)
+                .skip_while(|(score, _)| !above(min, &score.0))
+                .take_while(|(score, _)| below(max, &score.0))
+                .collect()
+        }
+        ZRangeBy::Lex(min, max) => ordered
+            .iter()
+            .skip_while(|(_, member)| !above(min, member))
+            .take_while(|(_, member)| below(max, member))
+            .collect(),
+    };
+    if rev {
+        members.reverse();
+    }
+    match limit {
+        Some((offset, _)) if offset < 0 => Vec::new(),
+        Some((offset, count)) => {
+            let count = if count < 0 {
+                usize::MAX
+            } else {
+                count as usize
+            };
+            members
+                .into_iter()
+                .skip(offset as usize)
+                .take(count)
+                .collect()
+        }
+        None => members,
+    }
+}
+
+/// A value is within the lower bound of a range
+fn above<T: PartialOrd>(min: &Bound<T>, value: &T) -> bool {
+    match min {
+        Bound::Included(min) => value >= min,
+        Bound::Excluded(min) => value > min,
+        Bound::Unbounded => true,
+    }
+}
+
+/// A value is within the upper bound of a range
+fn below<T: PartialOrd>(max: &Bound<T>, value: &T) -> bool {
+    match max {
+        Bound::Included(max) => value <= max,
+        Bound::Excluded(max) => value < max,
+        Bound::Unbounded => true,
+    }
+}
+
+/// How the scores of a member in several sorted sets are combined
+#[derive(Debug, Clone, Copy)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, current: f64, score: f64) -> f64 {
+        match self {
+            // inf + -inf is 0 like in redis
+            Aggregate::Sum => zero_if_nan(current + score),
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() {
+        0.0
+    } else {
+        score
+    }
+}
+
+/// Scores of the members of several keys, members of sets score 1 and missing keys are empty
+fn inputs<'a>(
+    storage: &'a mut Storage,
+    keys: &[RedisKey],
+) -> Result<Vec<HashMap<&'a BulkString, f64>>, &'static str> {
+    storage
+        .get_many(keys)
+        .into_iter()
+        .map(|entry| match entry.map(|entry| &entry.value) {
+            Some(Value::ZSet(zset)) => Ok(zset
+                .iter()
+                .map(|(member, score)| (member, *score))
+                .collect()),
+            Some(Value::Set(set)) => Ok(set.iter().map(|member| (member, 1.0)).collect()),
+            Some(_) => Err(WRONGTYPE),
+            None => Ok(HashMap::new()),
+        })
+        .collect()
+}
+
+/// Sorted set resulting of an operation on several keys
+/// Scores are multiplied by the weights and aggregated for the union and the intersection, the
+/// difference keeps the scores of the first key
+fn combine_scores(
+    storage: &mut Storage,
+    operation: Operation,
+    keys: &[RedisKey],
+    options: &ZCombineOptions,
+) -> Result<SortedSet, &'static str> {
+    let inputs = inputs(storage, keys)?;
+    let weighted = |score: f64, index: usize| match &options.weights {
+        Some(weights) => zero_if_nan(score * weights[index]),
+        None => score,
+    };
+    let mut scores: HashMap<&BulkString, f64> = HashMap::new();
+    let (first, rest) = match inputs.split_first() {
+        Some((first, rest)) => (first, rest),
+        None => return Ok(SortedSet::default()),
+    };
+    match operation {
+        Operation::Union => {
+            for (index, input) in inputs.iter().enumerate() {
+                for (member, score) in input {
+                    let score = weighted(*score, index);
+                    scores
+                        .entry(member)
+                        .and_modify(|current| *current = options.aggregate.apply(*current, score))
+                        .or_insert(score);
+                }
+            }
+        }
+        Operation::Inter => {
+            'members: for (member, score) in first {
+                let mut score = weighted(*score, 0);
+                for (index, input) in rest.iter().enumerate() {
+                    match input.get(member) {
+                        Some(other) => {
+                            score = options.aggregate.apply(score, weighted(*other, index + 1))
+                        }
+                        None => continue 'members,
+                    }
+                }
+                scores.insert(member, score);
+            }
+        }
+        Operation::Diff => {
+            for (member, score) in first {
+                if !rest.iter().any(|input| input.contains_key(member)) {
+                    scores.insert(member, *score);
+                }
+            }
+        }
+    }
+    let mut zset = SortedSet::default();
+    for (member, score) in scores {
+        zset.insert(member.clone(), score);
+    }
+    Ok(zset)
+}
+
+/// Reply the members resulting of an operation on several sorted sets, ordered by score
+pub fn combine(
+    storage: &mut Storage,
+    operation: Operation,
+    keys: &[RedisKey],
+    options: &ZCombineOptions,
+) -> Result<RespValue, &'static str> {
+    let zset = combine_scores(storage, operation, keys, options)?;
+    let mut reply = VecDeque::new();
+    for (Score(score), member) in zset.ordered() {
+        reply.push_back(RespValue::BulkString(member.clone()));
+        if options.with_scores {
+            reply.push_back(score_reply(*score));
+        }
+    }
+    Ok(RespValue::Array(reply))
+}
+
+/// Store the members resulting of an operation on several sorted sets, replacing the destination
+/// Replies the number of members stored, the destination is removed if there are none
+pub fn combine_store(
+    storage: &mut Storage,
+    operation: Operation,
+    destination: &RedisKey,
+    keys: &[RedisKey],
+    options: &ZCombineOptions,
+) -> Result<RespValue, &'static str> {
+    let zset = combine_scores(storage, operation, keys, options)?;
+    let len = zset.len();
+    if zset.is_empty() {
+        storage.remove(destination);
+    } else {
+        storage.set(destination.clone(), Value::ZSet(zset));
+    }
+    Ok(RespValue::Integer(len as i64))
+}